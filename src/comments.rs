@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+
+use crate::model::*;
+
+#[derive(Default)]
+struct Collector {
+    buffer: String,
+    has_comment: bool,
+    is_line_comment: bool,
+    can_attach_to_prev: bool,
+    prev_trailing: Option<String>,
+    detached: Vec<String>,
+}
+
+impl Collector {
+    fn new(can_attach_to_prev: bool) -> Collector {
+        Collector {
+            can_attach_to_prev,
+            ..Default::default()
+        }
+    }
+
+    fn line_buffer(&mut self) -> &mut String {
+        if self.has_comment && !self.is_line_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = true;
+        &mut self.buffer
+    }
+
+    fn block_buffer(&mut self) -> &mut String {
+        if self.has_comment {
+            self.flush();
+        }
+        self.has_comment = true;
+        self.is_line_comment = false;
+        &mut self.buffer
+    }
+
+    fn flush(&mut self) {
+        if self.has_comment {
+            let comment = std::mem::take(&mut self.buffer);
+            if self.can_attach_to_prev {
+                self.prev_trailing = Some(comment);
+                self.can_attach_to_prev = false;
+            } else {
+                self.detached.push(comment);
+            }
+        }
+        self.clear_buffer();
+    }
+
+    fn clear_buffer(&mut self) {
+        self.buffer.clear();
+        self.has_comment = false;
+    }
+}
+
+enum CommentStart {
+    Line,
+    Block,
+    None,
+}
+
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn try_consume(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace_no_newline(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(0x0b) | Some(0x0c) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn try_consume_comment_start(&mut self) -> CommentStart {
+        let rest = &self.text.as_bytes()[self.pos..];
+        if rest.starts_with(b"//") {
+            self.pos += 2;
+            CommentStart::Line
+        } else if rest.starts_with(b"/*") {
+            self.pos += 2;
+            CommentStart::Block
+        } else {
+            CommentStart::None
+        }
+    }
+
+    fn consume_line_comment(&mut self, content: &mut String) {
+        let rest = &self.text[self.pos..];
+        let len = rest.find('\n').map(|i| i + 1).unwrap_or_else(|| rest.len());
+        content.push_str(&rest[..len]);
+        self.pos += len;
+    }
+
+    // Mirrors protoc: continuation lines lose their leading whitespace and one `*`.
+    // Block comments nest like in the grammar, inner markers are kept in the content.
+    fn consume_block_comment(&mut self, content: &mut String) {
+        let mut start = self.pos;
+        let mut depth = 0;
+        loop {
+            let next = self.text.as_bytes().get(self.pos + 1).copied();
+            match self.peek() {
+                None => {
+                    content.push_str(&self.text[start..self.pos]);
+                    return;
+                }
+                Some(b'\n') => {
+                    self.pos += 1;
+                    content.push_str(&self.text[start..self.pos]);
+                    self.skip_whitespace_no_newline();
+                    if !self.text[self.pos..].starts_with("*/") {
+                        self.try_consume(b'*');
+                    } else if depth == 0 {
+                        self.pos += 2;
+                        return;
+                    }
+                    start = self.pos;
+                }
+                Some(b'*') if next == Some(b'/') => {
+                    if depth == 0 {
+                        content.push_str(&self.text[start..self.pos]);
+                        self.pos += 2;
+                        return;
+                    }
+                    depth -= 1;
+                    self.pos += 2;
+                }
+                Some(b'/') if next == Some(b'*') => {
+                    depth += 1;
+                    self.pos += 2;
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Option<(usize, usize)> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let c = self.peek()?;
+        if c.is_ascii_alphanumeric() || c == b'_' {
+            while let Some(c) = self.peek() {
+                if !(c.is_ascii_alphanumeric() || c == b'_') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        } else if c == b'"' || c == b'\'' {
+            self.pos += 1;
+            while let Some(d) = self.peek() {
+                self.pos += 1;
+                if d == b'\\' {
+                    self.pos += 1;
+                } else if d == c || d == b'\n' {
+                    break;
+                }
+            }
+            self.pos = self.pos.min(bytes.len());
+        } else {
+            self.pos += self.text[start..].chars().next().map_or(1, char::len_utf8);
+        }
+        Some((start, self.pos))
+    }
+}
+
+/// Comments split around every token following protoc's `Tokenizer::NextWithComments`.
+#[derive(Default)]
+pub(crate) struct CommentMap<'a> {
+    text: &'a str,
+    tokens: Vec<(usize, usize)>,
+    leading: HashMap<usize, String>,
+    trailing: HashMap<usize, String>,
+    detached: HashMap<usize, Vec<String>>,
+}
+
+impl<'a> CommentMap<'a> {
+    pub(crate) fn new(text: &'a str) -> CommentMap<'a> {
+        let mut map = CommentMap {
+            text,
+            ..Default::default()
+        };
+        let mut scanner = Scanner { text, pos: 0 };
+        let mut prev_end: Option<usize> = None;
+
+        loop {
+            let mut collector = Collector::new(prev_end.is_some());
+            let mut same_line = false;
+
+            if prev_end.is_some() {
+                scanner.skip_whitespace_no_newline();
+                match scanner.try_consume_comment_start() {
+                    CommentStart::Line => {
+                        scanner.consume_line_comment(collector.line_buffer());
+                        collector.flush();
+                    }
+                    CommentStart::Block => {
+                        scanner.consume_block_comment(collector.block_buffer());
+                        scanner.skip_whitespace_no_newline();
+                        if scanner.try_consume(b'\n') {
+                            collector.flush();
+                        } else {
+                            collector.clear_buffer();
+                            same_line = true;
+                        }
+                    }
+                    CommentStart::None => {
+                        same_line = !scanner.try_consume(b'\n');
+                    }
+                }
+            }
+
+            if !same_line {
+                loop {
+                    scanner.skip_whitespace_no_newline();
+                    match scanner.try_consume_comment_start() {
+                        CommentStart::Line => scanner.consume_line_comment(collector.line_buffer()),
+                        CommentStart::Block => {
+                            scanner.consume_block_comment(collector.block_buffer());
+                            scanner.skip_whitespace_no_newline();
+                            scanner.try_consume(b'\n');
+                        }
+                        CommentStart::None => {
+                            if scanner.try_consume(b'\n') {
+                                collector.flush();
+                                collector.can_attach_to_prev = false;
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let token = scanner.next_token();
+            let end_of_scope = match token {
+                Some((start, _)) => matches!(text.as_bytes()[start], b'}' | b']' | b')'),
+                None => true,
+            };
+            if !same_line && end_of_scope {
+                collector.flush();
+            }
+
+            if let (Some(end), Some(comment)) = (prev_end, collector.prev_trailing) {
+                map.trailing.insert(end, comment);
+            }
+            match token {
+                Some((start, end)) => {
+                    if !collector.detached.is_empty() {
+                        map.detached.insert(start, collector.detached);
+                    }
+                    if collector.has_comment {
+                        map.leading.insert(start, collector.buffer);
+                    }
+                    map.tokens.push((start, end));
+                    prev_end = Some(end);
+                }
+                None => break,
+            }
+        }
+
+        map
+    }
+
+    /// Comments of a declaration, block declarations take their trailing comment after `{`.
+    pub(crate) fn comments(&self, span: &Span, block: bool) -> Comments {
+        let start = span.byte_range.start;
+        let end = if block {
+            let first = self.tokens.partition_point(|t| t.0 < start);
+            self.tokens[first..]
+                .iter()
+                .take_while(|t| t.1 <= span.byte_range.end)
+                .find(|t| self.text.as_bytes()[t.0] == b'{')
+                .map_or(span.byte_range.end, |t| t.1)
+        } else {
+            span.byte_range.end
+        };
+
+        Comments {
+            leading: self.leading.get(&start).cloned(),
+            trailing: self.trailing.get(&end).cloned(),
+            detached: self.detached.get(&start).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+fn attach_enum(map: &CommentMap<'_>, e: &mut Enum) {
+    e.comments = map.comments(&e.span, true);
+    for field in e.fields.iter_mut() {
+        field.comments = map.comments(&field.span, false);
+    }
+}
+
+fn attach_extend(map: &CommentMap<'_>, extend: &mut Extend) {
+    for field in extend.fields.iter_mut() {
+        field.comments = map.comments(&field.span, false);
+    }
+}
+
+fn attach_message(map: &CommentMap<'_>, msg: &mut Message) {
+    msg.comments = map.comments(&msg.span, true);
+    for field in msg.fields.iter_mut() {
+        match field {
+            MessageField::Normal(f) => f.comments = map.comments(&f.span, false),
+            MessageField::Map(f) => f.comments = map.comments(&f.span, false),
+            MessageField::Oneof(o) => {
+                o.comments = map.comments(&o.span, true);
+                for f in o.fields.iter_mut() {
+                    f.comments = map.comments(&f.span, f.group.is_some());
+                    if let Some(m) = &mut f.group {
+                        attach_message(map, m);
+                    }
+                }
+            }
+            MessageField::Group(g) => {
+                g.comments = map.comments(&g.span, true);
+                attach_message(map, &mut g.message);
+            }
+            MessageField::Invalid => {}
+        }
+    }
+    for e in msg.inner_enums.iter_mut() {
+        attach_enum(map, e);
+    }
+    for m in msg.inner_messages.iter_mut() {
+        attach_message(map, m);
+    }
+    for extend in msg.extends.iter_mut() {
+        attach_extend(map, extend);
+    }
+}
+
+/// Attaches leading, trailing and detached comments to the elements of `proto`.
+pub(crate) fn attach(text: &str, proto: &mut ProtoFile) {
+    let map = CommentMap::new(text);
+
+    for e in proto.enums.iter_mut() {
+        attach_enum(&map, e);
+    }
+    for m in proto.messages.iter_mut() {
+        attach_message(&map, m);
+    }
+    for extend in proto.extends.iter_mut() {
+        attach_extend(&map, extend);
+    }
+    for s in proto.services.iter_mut() {
+        s.comments = map.comments(&s.span, true);
+        for rpc in s.rpcs.iter_mut() {
+            let block = text[rpc.span.byte_range.clone()].ends_with('}');
+            rpc.comments = map.comments(&rpc.span, block);
+        }
+    }
+}
@@ -0,0 +1,702 @@
+use std::fmt;
+use std::ops::Range;
+
+use pest::iterators::Pair;
+
+use crate::error::*;
+use crate::model::ProtoFile;
+use crate::parser::{parse_rule, proto_file_from_pairs, Rule};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Whitespace,
+    Comment,
+    /// Keywords, punctuation and the text of leaf rules.
+    Literal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+}
+
+impl SyntaxToken {
+    pub fn is_trivia(&self) -> bool {
+        self.kind != TokenKind::Literal
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            SyntaxElement::Node(node) => node.range.clone(),
+            SyntaxElement::Token(token) => token.range.clone(),
+        }
+    }
+}
+
+/// A grammar rule match, trivia around it belongs to the parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    rule: Rule,
+    range: Range<usize>,
+    children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn children(&self) -> &[SyntaxElement] {
+        &self.children
+    }
+
+    pub fn child_nodes(&self) -> impl DoubleEndedIterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// First direct child matching `rule`.
+    pub fn child(&self, rule: Rule) -> Option<&SyntaxNode> {
+        self.child_nodes().find(|n| n.rule == rule)
+    }
+
+    /// The node and every node below it, in source order.
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants { stack: vec![self] }
+    }
+
+    fn tokens<'a>(&'a self, out: &mut Vec<&'a SyntaxToken>) {
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => node.tokens(out),
+                SyntaxElement::Token(token) => out.push(token),
+            }
+        }
+    }
+}
+
+pub struct Descendants<'a> {
+    stack: Vec<&'a SyntaxNode>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a SyntaxNode;
+
+    fn next(&mut self) -> Option<&'a SyntaxNode> {
+        let node = self.stack.pop()?;
+        let children: Vec<_> = node.child_nodes().collect();
+        self.stack.extend(children.into_iter().rev());
+        Some(node)
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Length of the block comment `rest` starts with, nested comments included like the grammar.
+fn block_comment_len(rest: &str) -> usize {
+    let bytes = rest.as_bytes();
+    let mut depth = 0;
+    let mut pos = 0;
+
+    while pos + 1 < bytes.len() {
+        match &bytes[pos..pos + 2] {
+            b"/*" => {
+                depth += 1;
+                pos += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    return pos;
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+
+    rest.len()
+}
+
+// Splits text between rule matches into whitespace, comment and literal tokens.
+fn push_gap(text: &str, range: Range<usize>, out: &mut Vec<SyntaxElement>) {
+    let mut pos = range.start;
+
+    while pos < range.end {
+        let rest = &text[pos..range.end];
+        let (kind, len) = if rest.starts_with(char::is_whitespace) {
+            let len = rest
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len());
+            (TokenKind::Whitespace, len)
+        } else if rest.starts_with("//") {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with("/*") {
+            (TokenKind::Comment, block_comment_len(rest))
+        } else if rest.starts_with(is_word) {
+            (
+                TokenKind::Literal,
+                rest.find(|c| !is_word(c)).unwrap_or(rest.len()),
+            )
+        } else {
+            let len = rest.chars().next().map_or(1, char::len_utf8);
+            (TokenKind::Literal, len)
+        };
+        out.push(SyntaxElement::Token(SyntaxToken {
+            kind,
+            range: pos..pos + len,
+        }));
+        pos += len;
+    }
+}
+
+fn build(text: &str, pair: Pair<'_, Rule>) -> SyntaxNode {
+    let rule = pair.as_rule();
+    let range = pair.as_span().start()..pair.as_span().end();
+    let mut children = Vec::new();
+    let mut inner = pair.into_inner().peekable();
+
+    if inner.peek().is_none() {
+        children.push(SyntaxElement::Token(SyntaxToken {
+            kind: TokenKind::Literal,
+            range: range.clone(),
+        }));
+    } else {
+        let mut pos = range.start;
+        for pair in inner {
+            let node = build(text, pair);
+            push_gap(text, pos..node.range.start, &mut children);
+            pos = node.range.end;
+            children.push(SyntaxElement::Node(node));
+        }
+        push_gap(text, pos..range.end, &mut children);
+    }
+
+    // pest may end a match after trailing trivia, which is left to the parent.
+    while let Some(SyntaxElement::Token(token)) = children.last() {
+        if !token.is_trivia() {
+            break;
+        }
+        children.pop();
+    }
+    let end = children.last().map_or(range.start, |c| c.range().end);
+
+    SyntaxNode {
+        rule,
+        range: range.start..end,
+        children,
+    }
+}
+
+fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn indentation(text: &str, pos: usize) -> &str {
+    let start = line_start(text, pos);
+    let line = &text[start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Lossless syntax tree of a proto file: every byte of the source is covered by one token.
+///
+/// The `ProtoFile` is built from the same grammar match as the tree.
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    text: String,
+    children: Vec<SyntaxElement>,
+    proto: ProtoFile,
+}
+
+impl SyntaxTree {
+    /// Parses `text`, failing with the first error; grammar errors toggle pest's process-wide
+    /// error detail as described on `parse_proto_text`.
+    pub fn parse(text: impl Into<String>) -> Result<SyntaxTree> {
+        let text = text.into();
+        let mut children = Vec::new();
+        let mut pos = 0;
+        let pairs = parse_rule(Rule::file, &text)?;
+        let (proto, errors) = proto_file_from_pairs(&text, pairs.clone());
+        if let Some(e) = errors.into_iter().next() {
+            return Err(e);
+        }
+
+        for pair in pairs {
+            let node = build(&text, pair);
+            push_gap(&text, pos..node.range.start, &mut children);
+            pos = node.range.end;
+            children.push(SyntaxElement::Node(node));
+        }
+        push_gap(&text, pos..text.len(), &mut children);
+
+        Ok(SyntaxTree {
+            text,
+            children,
+            proto,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn children(&self) -> &[SyntaxElement] {
+        &self.children
+    }
+
+    pub fn node_text(&self, node: &SyntaxNode) -> &str {
+        &self.text[node.range.clone()]
+    }
+
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut out = Vec::new();
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => node.tokens(&mut out),
+                SyntaxElement::Token(token) => out.push(token),
+            }
+        }
+        out
+    }
+
+    pub fn proto_file(&self) -> &ProtoFile {
+        &self.proto
+    }
+
+    pub fn into_proto_file(self) -> ProtoFile {
+        self.proto
+    }
+
+    /// Every node of the tree, in source order.
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children
+            .iter()
+            .filter_map(|c| match c {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .flat_map(SyntaxNode::descendants)
+    }
+
+    fn name_of(&self, node: &SyntaxNode, rule: Rule) -> Option<&str> {
+        node.child(rule).map(|n| self.node_text(n))
+    }
+
+    fn definitions<'a>(&'a self, scope: Option<&'a SyntaxNode>) -> Vec<&'a SyntaxNode> {
+        match scope {
+            Some(msg) => msg
+                .child(Rule::messageBody)
+                .map(|body| body.child_nodes().collect())
+                .unwrap_or_default(),
+            None => self
+                .children
+                .iter()
+                .filter_map(|c| match c {
+                    SyntaxElement::Node(node) if node.rule == Rule::topLevelDef => {
+                        node.child_nodes().next()
+                    }
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    fn find_definition(&self, name: &str, rule: Rule) -> Option<&SyntaxNode> {
+        let mut scope = None;
+        let mut parts = name.split('.').peekable();
+
+        while let Some(part) = parts.next() {
+            let (rule, name_rule) = match (parts.peek(), rule) {
+                (None, Rule::Enum) => (Rule::Enum, Rule::enumName),
+                _ => (Rule::Message, Rule::messageName),
+            };
+            scope = self
+                .definitions(scope)
+                .into_iter()
+                .find(|n| n.rule == rule && self.name_of(n, name_rule) == Some(part));
+            scope?;
+        }
+
+        scope
+    }
+
+    /// A message by its dotted path inside the file, e.g. `Outer.Inner`.
+    pub fn find_message(&self, name: &str) -> Option<&SyntaxNode> {
+        self.find_definition(name, Rule::Message)
+    }
+
+    pub fn find_enum(&self, name: &str) -> Option<&SyntaxNode> {
+        self.find_definition(name, Rule::Enum)
+    }
+
+    /// A field, map, group or oneof member of `message` by name.
+    pub fn find_field<'a>(&self, message: &'a SyntaxNode, name: &str) -> Option<&'a SyntaxNode> {
+        let body = message.child(Rule::messageBody)?;
+
+        body.child_nodes()
+            .flat_map(|n| match n.rule {
+                Rule::oneof => n.child_nodes().collect(),
+                _ => vec![n],
+            })
+            .find(|n| {
+                let field_name = match n.rule {
+                    Rule::field | Rule::oneofField => self.name_of(n, Rule::fieldName),
+                    Rule::mapField => self.name_of(n, Rule::mapName),
+                    Rule::group => self.name_of(n, Rule::groupName),
+                    _ => None,
+                };
+                field_name == Some(name)
+            })
+    }
+
+    /// An option set directly on `node`, either an `option` statement or a bracketed option.
+    pub fn find_option<'a>(&self, node: &'a SyntaxNode, name: &str) -> Option<&'a SyntaxNode> {
+        node.child_nodes()
+            .flat_map(|n| match n.rule {
+                Rule::messageBody | Rule::enumBody | Rule::fieldOptions => {
+                    n.child_nodes().collect()
+                }
+                _ => vec![n],
+            })
+            .find(|n| {
+                matches!(
+                    n.rule,
+                    Rule::option | Rule::fieldOption | Rule::enumValueOption
+                ) && self.name_of(n, Rule::optionName).map(str::trim) == Some(name)
+            })
+    }
+
+    pub fn rewriter(&self) -> Rewriter<'_> {
+        Rewriter {
+            tree: self,
+            edits: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            f.write_str(&self.text[token.range.clone()])?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects edits against a `SyntaxTree`, text outside the edited ranges is kept as is.
+#[derive(Debug, Clone)]
+pub struct Rewriter<'a> {
+    tree: &'a SyntaxTree,
+    edits: Vec<(Range<usize>, String)>,
+}
+
+impl Rewriter<'_> {
+    pub fn replace_range(&mut self, range: Range<usize>, text: impl Into<String>) -> &mut Self {
+        self.edits.push((range, text.into()));
+        self
+    }
+
+    pub fn replace(&mut self, node: &SyntaxNode, text: impl Into<String>) -> &mut Self {
+        self.replace_range(node.range(), text)
+    }
+
+    pub fn insert_before(&mut self, node: &SyntaxNode, text: impl Into<String>) -> &mut Self {
+        self.replace_range(node.range.start..node.range.start, text)
+    }
+
+    pub fn insert_after(&mut self, node: &SyntaxNode, text: impl Into<String>) -> &mut Self {
+        self.replace_range(node.range.end..node.range.end, text)
+    }
+
+    /// Removes `node`, along with its line when nothing else is on it.
+    pub fn remove(&mut self, node: &SyntaxNode) -> &mut Self {
+        let text = self.tree.text();
+        let start = line_start(text, node.range.start);
+        let end = text[node.range.end..]
+            .find('\n')
+            .map_or(text.len(), |i| node.range.end + i + 1);
+
+        if text[start..node.range.start].trim().is_empty()
+            && text[node.range.end..end].trim().is_empty()
+        {
+            self.replace_range(start..end, "")
+        } else {
+            self.replace(node, "")
+        }
+    }
+
+    /// Replaces the value of an option node found by `SyntaxTree::find_option`.
+    ///
+    /// Fails when `option` has no value, i.e. is not an option node.
+    pub fn set_option_value(
+        &mut self,
+        option: &SyntaxNode,
+        value: impl Into<String>,
+    ) -> Result<&mut Self> {
+        match option.child(Rule::constant) {
+            Some(constant) => Ok(self.replace(constant, value)),
+            None => Err(Error::InvalidEditTarget {
+                start: option.range.start,
+                end: option.range.end,
+                message: "not an option".to_string(),
+            }),
+        }
+    }
+
+    /// Appends a declaration at the end of the body of a message, enum, oneof or service,
+    /// indented like the existing members.
+    ///
+    /// Fails when `parent` has no body closed by `}`.
+    pub fn append(&mut self, parent: &SyntaxNode, declaration: &str) -> Result<&mut Self> {
+        let text = self.tree.text();
+        let body = parent
+            .child(Rule::messageBody)
+            .or_else(|| parent.child(Rule::enumBody))
+            .unwrap_or(parent);
+        let close = match body.children.iter().rev().find_map(|c| match c {
+            SyntaxElement::Token(t) if &text[t.range.clone()] == "}" => Some(t.range.start),
+            _ => None,
+        }) {
+            Some(close) => close,
+            None => {
+                return Err(Error::InvalidEditTarget {
+                    start: parent.range.start,
+                    end: parent.range.end,
+                    message: "no body closed by \"}\"".to_string(),
+                })
+            }
+        };
+
+        let close_indent = indentation(text, close);
+        let member = body.child_nodes().rev().find(|n| {
+            !matches!(
+                n.rule,
+                Rule::messageName
+                    | Rule::enumName
+                    | Rule::oneofName
+                    | Rule::serviceName
+                    | Rule::emptyStatement
+            )
+        });
+        let indent = match member {
+            Some(member)
+                if text[line_start(text, member.range.start)..member.range.start]
+                    .trim()
+                    .is_empty() =>
+            {
+                indentation(text, member.range.start).to_string()
+            }
+            _ => format!("{}  ", close_indent),
+        };
+
+        if text[line_start(text, close)..close].trim().is_empty() {
+            let start = line_start(text, close);
+            Ok(self.replace_range(start..start, format!("{}{}\n", indent, declaration)))
+        } else {
+            let end = text[..close].trim_end_matches([' ', '\t']).len();
+            Ok(self.replace_range(
+                end..close,
+                format!("\n{}{}\n{}", indent, declaration, close_indent),
+            ))
+        }
+    }
+
+    /// Applies the edits to the source text.
+    ///
+    /// Fails on overlapping edits and on ranges outside the text or inside a character.
+    pub fn finish(&self) -> Result<String> {
+        let text = self.tree.text();
+        let mut edits: Vec<_> = self.edits.iter().collect();
+        edits.sort_by_key(|(range, _)| (range.start, range.end));
+
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        for (range, replacement) in edits {
+            if range.start > range.end
+                || range.end > text.len()
+                || !text.is_char_boundary(range.start)
+                || !text.is_char_boundary(range.end)
+            {
+                return Err(Error::InvalidEditRange {
+                    start: range.start,
+                    end: range.end,
+                });
+            }
+            if range.start < pos {
+                return Err(Error::OverlappingEdits {
+                    start: range.start,
+                    end: range.end,
+                });
+            }
+            out.push_str(&text[pos..range.start]);
+            out.push_str(replacement);
+            pos = range.end;
+        }
+        out.push_str(&text[pos..]);
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &str = r#"syntax = "proto3";
+// The package.
+package foo;
+
+message Foo {
+    option deprecated = false;   // keep me
+    int32  id = 1 ;
+    /* inline /* nested */ */ string name = 2 [json_name = "n"];
+    message Inner { bool ok = 1; }
+}
+
+enum Kind { A = 0; }
+"#;
+
+    #[test]
+    fn test_lossless() {
+        let tree = SyntaxTree::parse(TEXT).expect("parse failed");
+        assert_eq!(tree.to_string(), TEXT);
+
+        let mut pos = 0;
+        for token in tree.tokens() {
+            assert_eq!(token.range.start, pos);
+            pos = token.range.end;
+        }
+        assert_eq!(pos, TEXT.len());
+
+        let comments: Vec<_> = tree
+            .tokens()
+            .into_iter()
+            .filter(|t| t.kind == TokenKind::Comment)
+            .map(|t| &TEXT[t.range.clone()])
+            .collect();
+        assert_eq!(
+            comments,
+            vec!["// The package.", "// keep me", "/* inline /* nested */ */"]
+        );
+
+        let foo = tree.find_message("Foo").expect("message not found");
+        let name = tree.find_field(foo, "name").expect("field not found");
+        assert_eq!(tree.node_text(name), "string name = 2 [json_name = \"n\"];");
+        let inner = tree.find_message("Foo.Inner").expect("message not found");
+        assert_eq!(tree.node_text(inner), "message Inner { bool ok = 1; }");
+        assert!(tree.find_enum("Kind").is_some());
+        assert!(tree.find_message("Foo.Missing").is_none());
+        let proto = tree.proto_file();
+        assert_eq!(proto.messages[0].span.byte_range, foo.range());
+        assert_eq!(
+            proto.messages[0].inner_messages[0].span.byte_range,
+            inner.range()
+        );
+
+        let mut dirs = vec![std::path::PathBuf::from("./protos")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).expect("read_dir failed") {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let text = std::fs::read_to_string(path).expect("read failed");
+                let tree = SyntaxTree::parse(text.as_str()).expect("parse failed");
+                assert_eq!(tree.to_string(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let tree = SyntaxTree::parse(TEXT).expect("parse failed");
+        let foo = tree.find_message("Foo").expect("message not found");
+        let inner = tree.find_message("Foo.Inner").expect("message not found");
+        let kind = tree.find_enum("Kind").expect("enum not found");
+        let deprecated = tree
+            .find_option(foo, "deprecated")
+            .expect("option not found");
+        let name = tree.find_field(foo, "name").expect("field not found");
+        let json_name = tree
+            .find_option(name, "json_name")
+            .expect("option not found");
+        let id = tree.find_field(foo, "id").expect("field not found");
+
+        let mut rewriter = tree.rewriter();
+        rewriter
+            .set_option_value(deprecated, "true")
+            .and_then(|r| r.set_option_value(json_name, "\"full_name\""))
+            .and_then(|r| r.remove(id).append(foo, "repeated string tags = 3;"))
+            .and_then(|r| r.append(inner, "bool done = 2;"))
+            .and_then(|r| r.append(kind, "B = 1;"))
+            .expect("edit failed");
+        let text = rewriter.finish().expect("rewrite failed");
+
+        assert_eq!(
+            text,
+            r#"syntax = "proto3";
+// The package.
+package foo;
+
+message Foo {
+    option deprecated = true;   // keep me
+    /* inline /* nested */ */ string name = 2 [json_name = "full_name"];
+    message Inner { bool ok = 1;
+      bool done = 2;
+    }
+    repeated string tags = 3;
+}
+
+enum Kind { A = 0;
+  B = 1;
+}
+"#
+        );
+        let proto = SyntaxTree::parse(text)
+            .map(SyntaxTree::into_proto_file)
+            .expect("parse rewritten text failed");
+        assert_eq!(proto.messages[0].fields.len(), 2);
+
+        match tree.rewriter().replace(foo, "").remove(id).finish() {
+            Err(Error::OverlappingEdits { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match tree.rewriter().replace_range(5..500, "").finish() {
+            Err(Error::InvalidEditRange { start, end }) => assert_eq!((start, end), (5, 500)),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let tree = SyntaxTree::parse("// é\nsyntax = \"proto3\";").expect("parse failed");
+        match tree.rewriter().replace_range(4..5, "").finish() {
+            Err(Error::InvalidEditRange { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let syntax = tree.nodes().next().expect("node not found");
+        let mut rewriter = tree.rewriter();
+        match rewriter.set_option_value(syntax, "1").map(|_| ()) {
+            Err(Error::InvalidEditTarget { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match rewriter.append(syntax, "int32 a = 1;").map(|_| ()) {
+            Err(Error::InvalidEditTarget { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
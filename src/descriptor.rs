@@ -0,0 +1,1896 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use prost::Message as _;
+use prost_types::descriptor_proto::{
+    ExtensionRange as DescriptorExtensionRange, ReservedRange as DescriptorReservedRange,
+};
+use prost_types::enum_descriptor_proto::EnumReservedRange;
+use prost_types::field_descriptor_proto::{Label as DescriptorLabel, Type};
+use prost_types::field_options::{CType, JsType};
+use prost_types::file_options::OptimizeMode;
+use prost_types::method_options::IdempotencyLevel;
+use prost_types::source_code_info::Location;
+use prost_types::uninterpreted_option::NamePart;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumOptions, EnumValueDescriptorProto, EnumValueOptions,
+    ExtensionRangeOptions, FieldDescriptorProto, FieldOptions, FileDescriptorProto,
+    FileDescriptorSet, FileOptions, MessageOptions, MethodDescriptorProto, MethodOptions,
+    OneofDescriptorProto, OneofOptions, ServiceDescriptorProto, ServiceOptions, SourceCodeInfo,
+    UninterpretedOption,
+};
+
+use crate::error::*;
+use crate::literal::unescape;
+use crate::loader::FileSet;
+use crate::model::*;
+use crate::parser::parse_aggregate_text;
+
+// Field numbers of descriptor.proto, used to build source code info paths.
+const FILE_PACKAGE: i32 = 2;
+const FILE_DEPENDENCY: i32 = 3;
+const FILE_MESSAGE_TYPE: i32 = 4;
+const FILE_ENUM_TYPE: i32 = 5;
+const FILE_SERVICE: i32 = 6;
+const FILE_EXTENSION: i32 = 7;
+const MESSAGE_FIELD: i32 = 2;
+const MESSAGE_NESTED_TYPE: i32 = 3;
+const MESSAGE_ENUM_TYPE: i32 = 4;
+const MESSAGE_EXTENSION: i32 = 6;
+const MESSAGE_ONEOF_DECL: i32 = 8;
+const ENUM_VALUE: i32 = 2;
+const SERVICE_METHOD: i32 = 2;
+
+fn join(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// protoc's `ToJsonName`: underscores are dropped and the following letter is upper-cased.
+pub fn json_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Name of the nested message protoc generates for a map field, `foo_bar` -> `FooBarEntry`.
+pub fn map_entry_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 5);
+    let mut upper = true;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out.push_str("Entry");
+
+    out
+}
+
+fn simple_name(opt: &ProtoOption) -> Option<&str> {
+    match opt.name.parts.as_slice() {
+        [part] if !part.is_extension => Some(&part.name),
+        _ => None,
+    }
+}
+
+fn set<T>(slot: &mut Option<T>, value: Option<T>) -> bool {
+    let known = value.is_some();
+    if known {
+        *slot = value;
+    }
+    known
+}
+
+fn bool_value(value: &Constant) -> Option<bool> {
+    match value {
+        Constant::Bool(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn str_value(value: &Constant) -> Option<String> {
+    match value {
+        Constant::Str(v) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+fn enum_value<E: Into<i32>>(value: &Constant, from_str: fn(&str) -> Option<E>) -> Option<i32> {
+    match value {
+        Constant::Ident(v) => from_str(v).map(Into::into),
+        _ => None,
+    }
+}
+
+// The text between the braces, as protoc stores it in `aggregate_value`.
+fn aggregate_text(value: &AggregateValue) -> String {
+    value
+        .fields
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn uninterpreted(opt: &ProtoOption) -> UninterpretedOption {
+    let mut out = UninterpretedOption {
+        name: opt
+            .name
+            .parts
+            .iter()
+            .map(|part| NamePart {
+                name_part: part.name.clone(),
+                is_extension: part.is_extension,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    match &opt.value {
+        Constant::Ident(v) => out.identifier_value = Some(v.clone()),
+        Constant::Bool(v) => out.identifier_value = Some(v.to_string()),
+        Constant::Int(v) if *v < 0 => out.negative_int_value = Some(*v),
+        Constant::Int(v) => out.positive_int_value = Some(*v as u64),
+        Constant::Uint(v) => out.positive_int_value = Some(*v),
+        Constant::Float(v) => out.double_value = Some(*v),
+        Constant::Str(v) => out.string_value = Some(v.clone().into_bytes()),
+        Constant::Bytes(v) => out.string_value = Some(v.clone()),
+        Constant::Aggregate(v) => out.aggregate_value = Some(aggregate_text(v)),
+    }
+
+    out
+}
+
+#[allow(deprecated)]
+fn file_options(options: &[ProtoOption]) -> Option<FileOptions> {
+    let mut out = FileOptions::default();
+
+    for opt in options {
+        let v = &opt.value;
+        let known = match simple_name(opt) {
+            Some("java_package") => set(&mut out.java_package, str_value(v)),
+            Some("java_outer_classname") => set(&mut out.java_outer_classname, str_value(v)),
+            Some("java_multiple_files") => set(&mut out.java_multiple_files, bool_value(v)),
+            Some("java_generate_equals_and_hash") => {
+                set(&mut out.java_generate_equals_and_hash, bool_value(v))
+            }
+            Some("java_string_check_utf8") => set(&mut out.java_string_check_utf8, bool_value(v)),
+            Some("optimize_for") => set(
+                &mut out.optimize_for,
+                enum_value(v, OptimizeMode::from_str_name),
+            ),
+            Some("go_package") => set(&mut out.go_package, str_value(v)),
+            Some("cc_generic_services") => set(&mut out.cc_generic_services, bool_value(v)),
+            Some("java_generic_services") => set(&mut out.java_generic_services, bool_value(v)),
+            Some("py_generic_services") => set(&mut out.py_generic_services, bool_value(v)),
+            Some("deprecated") => set(&mut out.deprecated, bool_value(v)),
+            Some("cc_enable_arenas") => set(&mut out.cc_enable_arenas, bool_value(v)),
+            Some("objc_class_prefix") => set(&mut out.objc_class_prefix, str_value(v)),
+            Some("csharp_namespace") => set(&mut out.csharp_namespace, str_value(v)),
+            Some("swift_prefix") => set(&mut out.swift_prefix, str_value(v)),
+            Some("php_class_prefix") => set(&mut out.php_class_prefix, str_value(v)),
+            Some("php_namespace") => set(&mut out.php_namespace, str_value(v)),
+            Some("php_metadata_namespace") => set(&mut out.php_metadata_namespace, str_value(v)),
+            Some("ruby_package") => set(&mut out.ruby_package, str_value(v)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn message_options(options: &[ProtoOption]) -> Option<MessageOptions> {
+    let mut out = MessageOptions::default();
+
+    for opt in options {
+        let v = &opt.value;
+        let known = match simple_name(opt) {
+            Some("message_set_wire_format") => set(&mut out.message_set_wire_format, bool_value(v)),
+            Some("no_standard_descriptor_accessor") => {
+                set(&mut out.no_standard_descriptor_accessor, bool_value(v))
+            }
+            Some("deprecated") => set(&mut out.deprecated, bool_value(v)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+// `default` and `json_name` are pseudo-options stored on the field itself.
+fn field_options(options: &[ProtoOption]) -> Option<FieldOptions> {
+    let mut out = FieldOptions::default();
+
+    for opt in options {
+        let v = &opt.value;
+        let known = match simple_name(opt) {
+            Some("default") | Some("json_name") => true,
+            Some("ctype") => set(&mut out.ctype, enum_value(v, CType::from_str_name)),
+            Some("packed") => set(&mut out.packed, bool_value(v)),
+            Some("jstype") => set(&mut out.jstype, enum_value(v, JsType::from_str_name)),
+            Some("lazy") => set(&mut out.lazy, bool_value(v)),
+            Some("deprecated") => set(&mut out.deprecated, bool_value(v)),
+            Some("weak") => set(&mut out.weak, bool_value(v)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn enum_options(options: &[ProtoOption]) -> Option<EnumOptions> {
+    let mut out = EnumOptions::default();
+
+    for opt in options {
+        let v = &opt.value;
+        let known = match simple_name(opt) {
+            Some("allow_alias") => set(&mut out.allow_alias, bool_value(v)),
+            Some("deprecated") => set(&mut out.deprecated, bool_value(v)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn enum_value_options(options: &[ProtoOption]) -> Option<EnumValueOptions> {
+    let mut out = EnumValueOptions::default();
+
+    for opt in options {
+        let known = match simple_name(opt) {
+            Some("deprecated") => set(&mut out.deprecated, bool_value(&opt.value)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn service_options(options: &[ProtoOption]) -> Option<ServiceOptions> {
+    let mut out = ServiceOptions::default();
+
+    for opt in options {
+        let known = match simple_name(opt) {
+            Some("deprecated") => set(&mut out.deprecated, bool_value(&opt.value)),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn method_options(options: &[ProtoOption]) -> Option<MethodOptions> {
+    let mut out = MethodOptions::default();
+
+    for opt in options {
+        let v = &opt.value;
+        let known = match simple_name(opt) {
+            Some("deprecated") => set(&mut out.deprecated, bool_value(v)),
+            Some("idempotency_level") => set(
+                &mut out.idempotency_level,
+                enum_value(v, IdempotencyLevel::from_str_name),
+            ),
+            _ => false,
+        };
+        if !known {
+            out.uninterpreted_option.push(uninterpreted(opt));
+        }
+    }
+
+    Some(out).filter(|o| *o != Default::default())
+}
+
+fn extension_range_options(options: &[ProtoOption]) -> Option<ExtensionRangeOptions> {
+    Some(ExtensionRangeOptions {
+        uninterpreted_option: options.iter().map(uninterpreted).collect(),
+    })
+    .filter(|o| !o.uninterpreted_option.is_empty())
+}
+
+fn oneof_options(options: &[ProtoOption]) -> Option<OneofOptions> {
+    Some(OneofOptions {
+        uninterpreted_option: options.iter().map(uninterpreted).collect(),
+    })
+    .filter(|o| !o.uninterpreted_option.is_empty())
+}
+
+fn label(label: Label) -> i32 {
+    match label {
+        Label::Implicit | Label::Optional => DescriptorLabel::Optional.into(),
+        Label::Repeated => DescriptorLabel::Repeated.into(),
+        Label::Required => DescriptorLabel::Required.into(),
+    }
+}
+
+// Unlinked `MessageOrEnum` names are kept as is with the type left unset, like protoc does.
+fn field_type(field_type: &NormalFieldType) -> (Option<i32>, Option<String>) {
+    let scalar = |t: Type| (Some(t.into()), None);
+
+    match field_type {
+        NormalFieldType::Double => scalar(Type::Double),
+        NormalFieldType::Float => scalar(Type::Float),
+        NormalFieldType::Int32 => scalar(Type::Int32),
+        NormalFieldType::Int64 => scalar(Type::Int64),
+        NormalFieldType::Uint32 => scalar(Type::Uint32),
+        NormalFieldType::Uint64 => scalar(Type::Uint64),
+        NormalFieldType::Sint32 => scalar(Type::Sint32),
+        NormalFieldType::Sint64 => scalar(Type::Sint64),
+        NormalFieldType::Fixed32 => scalar(Type::Fixed32),
+        NormalFieldType::Fixed64 => scalar(Type::Fixed64),
+        NormalFieldType::Sfixed32 => scalar(Type::Sfixed32),
+        NormalFieldType::Sfixed64 => scalar(Type::Sfixed64),
+        NormalFieldType::Bool => scalar(Type::Bool),
+        NormalFieldType::Str => scalar(Type::String),
+        NormalFieldType::Bytes => scalar(Type::Bytes),
+        NormalFieldType::Message(name) => (Some(Type::Message.into()), Some(name.clone())),
+        NormalFieldType::Enum(name) => (Some(Type::Enum.into()), Some(name.clone())),
+        NormalFieldType::MessageOrEnum(name) => (None, Some(name.clone())),
+        NormalFieldType::Invalid => (None, None),
+    }
+}
+
+fn map_key_type(key_type: &MapFieldKeyType) -> NormalFieldType {
+    match key_type {
+        MapFieldKeyType::Int32 => NormalFieldType::Int32,
+        MapFieldKeyType::Int64 => NormalFieldType::Int64,
+        MapFieldKeyType::Uint32 => NormalFieldType::Uint32,
+        MapFieldKeyType::Uint64 => NormalFieldType::Uint64,
+        MapFieldKeyType::Sint32 => NormalFieldType::Sint32,
+        MapFieldKeyType::Sint64 => NormalFieldType::Sint64,
+        MapFieldKeyType::Fixed32 => NormalFieldType::Fixed32,
+        MapFieldKeyType::Fixed64 => NormalFieldType::Fixed64,
+        MapFieldKeyType::Sfixed32 => NormalFieldType::Sfixed32,
+        MapFieldKeyType::Sfixed64 => NormalFieldType::Sfixed64,
+        MapFieldKeyType::Bool => NormalFieldType::Bool,
+        MapFieldKeyType::Str => NormalFieldType::Str,
+        MapFieldKeyType::Invalid => NormalFieldType::Invalid,
+    }
+}
+
+// protoc's `CEscape`, used for `bytes` defaults and quoted names when writing proto text.
+pub(crate) fn c_escape(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+
+    for b in bytes.iter() {
+        match b {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'"' => out.push_str("\\\""),
+            b'\'' => out.push_str("\\'"),
+            b'\\' => out.push_str("\\\\"),
+            b' '..=b'~' => out.push(*b as char),
+            b => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+
+    out
+}
+
+fn default_value(field_type: &NormalFieldType, value: &Constant) -> String {
+    match value {
+        Constant::Str(s) if *field_type == NormalFieldType::Bytes => c_escape(s.as_bytes()),
+        Constant::Bytes(v) => c_escape(v),
+        Constant::Str(s) | Constant::Ident(s) => s.clone(),
+        Constant::Aggregate(v) => v.to_string(),
+        Constant::Bool(v) => v.to_string(),
+        Constant::Int(v) => v.to_string(),
+        Constant::Uint(v) => v.to_string(),
+        Constant::Float(v) if v.is_nan() => "nan".to_string(),
+        Constant::Float(v) if v.is_infinite() => if *v > 0.0 { "inf" } else { "-inf" }.to_string(),
+        Constant::Float(v) => simple_dtoa(*v),
+    }
+}
+
+// protoc's SimpleDtoa: `%.15g`, or `%.17g` when that does not round-trip.
+fn simple_dtoa(v: f64) -> String {
+    let short = format_g(v, 15);
+    if short.parse::<f64>() == Ok(v) {
+        short
+    } else {
+        format_g(v, 17)
+    }
+}
+
+// C's `%.{precision}g`.
+fn format_g(v: f64, precision: usize) -> String {
+    if v == 0.0 {
+        return if v.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let strip = |s: &str| -> String {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+
+    let sci = format!("{:.*e}", precision - 1, v);
+    let (mantissa, exponent) = sci.split_at(sci.find('e').unwrap_or(sci.len()));
+    let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", strip(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        strip(&format!("{:.*}", decimals, v))
+    }
+}
+
+fn field_json_name(name: &str, options: &[ProtoOption]) -> String {
+    options
+        .iter()
+        .find(|o| o.name == "json_name")
+        .and_then(|o| str_value(&o.value))
+        .unwrap_or_else(|| json_name(name))
+}
+
+// Messages nested in a message, protoc numbers them in declaration order.
+enum Nested<'a> {
+    Message(&'a Message),
+    Group(&'a Group),
+    Map(&'a MapField),
+}
+
+impl Nested<'_> {
+    fn start(&self) -> (usize, usize) {
+        match self {
+            Nested::Message(m) => m.span.start,
+            Nested::Group(g) => g.span.start,
+            Nested::Map(f) => f.span.start,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    locations: Vec<Location>,
+}
+
+impl Builder {
+    fn locate(&mut self, path: &[i32], span: &Span, comments: Option<&Comments>) {
+        // Elements built in code have no position in any source file.
+        if span.start.0 == 0 {
+            return;
+        }
+
+        let mut location = Location {
+            path: path.to_vec(),
+            span: vec![span.start.0 as i32 - 1, span.start.1 as i32 - 1],
+            ..Default::default()
+        };
+        if span.end.0 != span.start.0 {
+            location.span.push(span.end.0 as i32 - 1);
+        }
+        location.span.push(span.end.1 as i32 - 1);
+        if let Some(comments) = comments {
+            location.leading_comments = comments.leading.clone();
+            location.trailing_comments = comments.trailing.clone();
+            location.leading_detached_comments = comments.detached.clone();
+        }
+        self.locations.push(location);
+    }
+
+    fn field(&mut self, f: &NormalField, path: &[i32]) -> FieldDescriptorProto {
+        self.locate(path, &f.span, Some(&f.comments));
+        let (r#type, type_name) = field_type(&f.field_type);
+
+        FieldDescriptorProto {
+            name: Some(f.name.clone()),
+            number: Some(f.number as i32),
+            label: Some(label(f.label)),
+            r#type,
+            type_name,
+            default_value: f.default_value().map(|v| default_value(&f.field_type, v)),
+            json_name: Some(field_json_name(&f.name, &f.options)),
+            options: field_options(&f.options),
+            proto3_optional: Some(true).filter(|_| f.proto3_optional),
+            ..Default::default()
+        }
+    }
+
+    fn extend(&mut self, extend: &Extend, path: &[i32], out: &mut Vec<FieldDescriptorProto>) {
+        self.locate(path, &extend.span, None);
+        for f in extend.fields.iter() {
+            let path = [path, &[out.len() as i32]].concat();
+            out.push(FieldDescriptorProto {
+                extendee: Some(extend.extendee.clone()),
+                ..self.field(f, &path)
+            });
+        }
+    }
+
+    fn map_entry(&mut self, f: &MapField) -> DescriptorProto {
+        let entry_field = |name: &str, number: i32, field_type: &NormalFieldType| {
+            let (r#type, type_name) = self::field_type(field_type);
+            FieldDescriptorProto {
+                name: Some(name.to_string()),
+                number: Some(number),
+                label: Some(DescriptorLabel::Optional.into()),
+                r#type,
+                type_name,
+                json_name: Some(name.to_string()),
+                ..Default::default()
+            }
+        };
+
+        DescriptorProto {
+            name: Some(map_entry_name(&f.name)),
+            field: vec![
+                entry_field("key", 1, &map_key_type(&f.key_type)),
+                entry_field("value", 2, &f.value_type),
+            ],
+            options: Some(MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn enumeration(&mut self, e: &Enum, path: &[i32]) -> EnumDescriptorProto {
+        self.locate(path, &e.span, Some(&e.comments));
+        let mut out = EnumDescriptorProto {
+            name: Some(e.name.clone()),
+            options: enum_options(&e.options),
+            reserved_range: e
+                .reserved_ranges
+                .iter()
+                .map(|r| EnumReservedRange {
+                    start: Some(r.start),
+                    end: Some(r.end),
+                })
+                .collect(),
+            reserved_name: e.reserved_names.clone(),
+            ..Default::default()
+        };
+
+        for (i, field) in e.fields.iter().enumerate() {
+            let path = [path, &[ENUM_VALUE, i as i32]].concat();
+            self.locate(&path, &field.span, Some(&field.comments));
+            out.value.push(EnumValueDescriptorProto {
+                name: Some(field.name.clone()),
+                number: Some(field.value),
+                options: enum_value_options(&field.options),
+            });
+        }
+
+        out
+    }
+
+    fn message(&mut self, msg: &Message, scope: &str, path: &[i32]) -> DescriptorProto {
+        self.locate(path, &msg.span, Some(&msg.comments));
+        let scope = join(scope, &msg.name);
+        let mut out = DescriptorProto {
+            name: Some(msg.name.clone()),
+            options: message_options(&msg.options),
+            extension_range: msg
+                .extension_ranges
+                .iter()
+                .map(|r| DescriptorExtensionRange {
+                    start: Some(r.start),
+                    end: Some(r.end + 1),
+                    options: extension_range_options(&r.options),
+                })
+                .collect(),
+            reserved_range: msg
+                .reserved_ranges
+                .iter()
+                .map(|r| DescriptorReservedRange {
+                    start: Some(r.start),
+                    end: Some(r.end + 1),
+                })
+                .collect(),
+            reserved_name: msg.reserved_names.clone(),
+            ..Default::default()
+        };
+
+        let oneofs = msg
+            .fields
+            .iter()
+            .filter(|f| matches!(f, MessageField::Oneof(_)))
+            .count();
+        let mut synthetic = Vec::new();
+        let mut nested: Vec<Nested<'_>> = msg.inner_messages.iter().map(Nested::Message).collect();
+
+        for field in msg.fields.iter() {
+            let field_path = [path, &[MESSAGE_FIELD, out.field.len() as i32]].concat();
+            match field {
+                MessageField::Normal(f) => {
+                    let mut field = self.field(f, &field_path);
+                    if let Some(name) = &f.synthetic_oneof {
+                        field.oneof_index = Some((oneofs + synthetic.len()) as i32);
+                        synthetic.push(name.clone());
+                    }
+                    out.field.push(field);
+                }
+                MessageField::Oneof(o) => {
+                    let index = out.oneof_decl.len() as i32;
+                    self.locate(
+                        &[path, &[MESSAGE_ONEOF_DECL, index]].concat(),
+                        &o.span,
+                        Some(&o.comments),
+                    );
+                    out.oneof_decl.push(OneofDescriptorProto {
+                        name: Some(o.name.clone()),
+                        options: oneof_options(&o.options),
+                    });
+                    for f in o.fields.iter() {
+                        let field_path = [path, &[MESSAGE_FIELD, out.field.len() as i32]].concat();
+                        self.locate(&field_path, &f.span, Some(&f.comments));
+                        let (r#type, type_name) = match &f.group {
+                            Some(m) => {
+                                nested.push(Nested::Message(m));
+                                (
+                                    Some(Type::Group.into()),
+                                    Some(format!(".{}.{}", scope, m.name)),
+                                )
+                            }
+                            None => field_type(&f.field_type),
+                        };
+                        out.field.push(FieldDescriptorProto {
+                            name: Some(f.name.clone()),
+                            number: Some(f.number as i32),
+                            label: Some(DescriptorLabel::Optional.into()),
+                            r#type,
+                            type_name,
+                            oneof_index: Some(index),
+                            default_value: f
+                                .default_value()
+                                .map(|v| default_value(&f.field_type, v)),
+                            json_name: Some(field_json_name(&f.name, &f.options)),
+                            options: field_options(&f.options),
+                            ..Default::default()
+                        });
+                    }
+                }
+                MessageField::Map(f) => {
+                    self.locate(&field_path, &f.span, Some(&f.comments));
+                    out.field.push(FieldDescriptorProto {
+                        name: Some(f.name.clone()),
+                        number: Some(f.number as i32),
+                        label: Some(DescriptorLabel::Repeated.into()),
+                        r#type: Some(Type::Message.into()),
+                        type_name: Some(format!(".{}.{}", scope, map_entry_name(&f.name))),
+                        json_name: Some(field_json_name(&f.name, &f.options)),
+                        options: field_options(&f.options),
+                        ..Default::default()
+                    });
+                    nested.push(Nested::Map(f));
+                }
+                MessageField::Group(g) => {
+                    self.locate(&field_path, &g.span, Some(&g.comments));
+                    let name = g.field_name();
+                    out.field.push(FieldDescriptorProto {
+                        name: Some(name.clone()),
+                        number: Some(g.number as i32),
+                        label: Some(label(g.label)),
+                        r#type: Some(Type::Group.into()),
+                        type_name: Some(format!(".{}.{}", scope, g.name)),
+                        json_name: Some(field_json_name(&name, &g.options)),
+                        options: field_options(&g.options),
+                        ..Default::default()
+                    });
+                    nested.push(Nested::Group(g));
+                }
+                MessageField::Invalid => {}
+            }
+        }
+        out.oneof_decl
+            .extend(synthetic.into_iter().map(|name| OneofDescriptorProto {
+                name: Some(name),
+                options: None,
+            }));
+
+        nested.sort_by_key(Nested::start);
+        for (i, n) in nested.iter().enumerate() {
+            let path = [path, &[MESSAGE_NESTED_TYPE, i as i32]].concat();
+            let m = match n {
+                Nested::Message(m) => self.message(m, &scope, &path),
+                Nested::Group(g) => self.message(&g.message, &scope, &path),
+                Nested::Map(f) => self.map_entry(f),
+            };
+            out.nested_type.push(m);
+        }
+        for (i, e) in msg.inner_enums.iter().enumerate() {
+            let path = [path, &[MESSAGE_ENUM_TYPE, i as i32]].concat();
+            out.enum_type.push(self.enumeration(e, &path));
+        }
+        for extend in msg.extends.iter() {
+            let path = [path, &[MESSAGE_EXTENSION]].concat();
+            self.extend(extend, &path, &mut out.extension);
+        }
+
+        out
+    }
+
+    fn service(&mut self, s: &Service, path: &[i32]) -> ServiceDescriptorProto {
+        self.locate(path, &s.span, Some(&s.comments));
+        let mut out = ServiceDescriptorProto {
+            name: Some(s.name.clone()),
+            options: service_options(&s.options),
+            ..Default::default()
+        };
+
+        for (i, rpc) in s.rpcs.iter().enumerate() {
+            let path = [path, &[SERVICE_METHOD, i as i32]].concat();
+            self.locate(&path, &rpc.span, Some(&rpc.comments));
+            out.method.push(MethodDescriptorProto {
+                name: Some(rpc.name.clone()),
+                input_type: Some(rpc.request.clone()),
+                output_type: Some(rpc.response.clone()),
+                options: method_options(&rpc.options),
+                client_streaming: Some(true).filter(|_| rpc.client_streaming),
+                server_streaming: Some(true).filter(|_| rpc.server_streaming),
+            });
+        }
+
+        out
+    }
+}
+
+/// Converts `proto` to a `FileDescriptorProto`, type references should already be linked (see `link`).
+///
+/// Edition files are rejected: prost-types has no `edition` or `features` fields to describe them.
+pub fn file_descriptor(proto: &ProtoFile) -> Result<FileDescriptorProto> {
+    let syntax = match proto.syntax {
+        Syntax::Proto2 => None,
+        Syntax::Proto3 => Some("proto3".to_string()),
+        Syntax::Edition(edition) => {
+            return Err(Error::UnsupportedEdition {
+                edition: match edition {
+                    Edition::Edition2023 => "2023",
+                    Edition::Edition2024 => "2024",
+                }
+                .to_string(),
+                location: Default::default(),
+            })
+        }
+    };
+    let mut builder: Builder = Default::default();
+    let mut out = FileDescriptorProto {
+        name: Some(proto.filename.clone()).filter(|n| !n.is_empty()),
+        package: Some(proto.package.clone()).filter(|p| !p.is_empty()),
+        options: file_options(&proto.options),
+        syntax,
+        ..Default::default()
+    };
+
+    if !proto.package.is_empty() {
+        builder.locate(&[FILE_PACKAGE], &proto.package_span, None);
+    }
+    for (i, import) in proto.import.iter().enumerate() {
+        builder.locate(&[FILE_DEPENDENCY, i as i32], &import.span, None);
+        match import.import_type {
+            ImportType::Public => out.public_dependency.push(i as i32),
+            ImportType::Weak => out.weak_dependency.push(i as i32),
+            ImportType::Normal => {}
+        }
+        out.dependency.push(import.proto_file.clone());
+    }
+    for (i, m) in proto.messages.iter().enumerate() {
+        let m = builder.message(m, &proto.package, &[FILE_MESSAGE_TYPE, i as i32]);
+        out.message_type.push(m);
+    }
+    for (i, e) in proto.enums.iter().enumerate() {
+        let e = builder.enumeration(e, &[FILE_ENUM_TYPE, i as i32]);
+        out.enum_type.push(e);
+    }
+    for (i, s) in proto.services.iter().enumerate() {
+        let s = builder.service(s, &[FILE_SERVICE, i as i32]);
+        out.service.push(s);
+    }
+    for extend in proto.extends.iter() {
+        builder.extend(extend, &[FILE_EXTENSION], &mut out.extension);
+    }
+    if !builder.locations.is_empty() {
+        out.source_code_info = Some(SourceCodeInfo {
+            location: builder.locations,
+        });
+    }
+
+    Ok(out)
+}
+
+/// Descriptors of every file in `set`, each file after its imports.
+pub fn file_descriptor_set(set: &FileSet) -> Result<FileDescriptorSet> {
+    let file = set
+        .files()
+        .iter()
+        .map(|proto| {
+            file_descriptor(proto).map_err(|e| Error::FileError {
+                filename: proto.filename.clone(),
+                source: Box::new(e),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(FileDescriptorSet { file })
+}
+
+fn field_type_from(r#type: Option<Type>, type_name: &str) -> NormalFieldType {
+    match r#type {
+        Some(Type::Double) => NormalFieldType::Double,
+        Some(Type::Float) => NormalFieldType::Float,
+        Some(Type::Int32) => NormalFieldType::Int32,
+        Some(Type::Int64) => NormalFieldType::Int64,
+        Some(Type::Uint32) => NormalFieldType::Uint32,
+        Some(Type::Uint64) => NormalFieldType::Uint64,
+        Some(Type::Sint32) => NormalFieldType::Sint32,
+        Some(Type::Sint64) => NormalFieldType::Sint64,
+        Some(Type::Fixed32) => NormalFieldType::Fixed32,
+        Some(Type::Fixed64) => NormalFieldType::Fixed64,
+        Some(Type::Sfixed32) => NormalFieldType::Sfixed32,
+        Some(Type::Sfixed64) => NormalFieldType::Sfixed64,
+        Some(Type::Bool) => NormalFieldType::Bool,
+        Some(Type::String) => NormalFieldType::Str,
+        Some(Type::Bytes) => NormalFieldType::Bytes,
+        Some(Type::Message) | Some(Type::Group) => NormalFieldType::Message(type_name.to_string()),
+        Some(Type::Enum) => NormalFieldType::Enum(type_name.to_string()),
+        None if !type_name.is_empty() => NormalFieldType::MessageOrEnum(type_name.to_string()),
+        None => NormalFieldType::Invalid,
+    }
+}
+
+fn map_key_type_from(field_type: &NormalFieldType) -> MapFieldKeyType {
+    match field_type {
+        NormalFieldType::Int32 => MapFieldKeyType::Int32,
+        NormalFieldType::Int64 => MapFieldKeyType::Int64,
+        NormalFieldType::Uint32 => MapFieldKeyType::Uint32,
+        NormalFieldType::Uint64 => MapFieldKeyType::Uint64,
+        NormalFieldType::Sint32 => MapFieldKeyType::Sint32,
+        NormalFieldType::Sint64 => MapFieldKeyType::Sint64,
+        NormalFieldType::Fixed32 => MapFieldKeyType::Fixed32,
+        NormalFieldType::Fixed64 => MapFieldKeyType::Fixed64,
+        NormalFieldType::Sfixed32 => MapFieldKeyType::Sfixed32,
+        NormalFieldType::Sfixed64 => MapFieldKeyType::Sfixed64,
+        NormalFieldType::Bool => MapFieldKeyType::Bool,
+        NormalFieldType::Str => MapFieldKeyType::Str,
+        _ => MapFieldKeyType::Invalid,
+    }
+}
+
+fn default_value_from(field_type: &NormalFieldType, value: &str) -> Constant {
+    match field_type {
+        NormalFieldType::Str => Constant::Str(value.to_string()),
+        // `c_escape` output is valid string literal syntax
+        NormalFieldType::Bytes => unescape(value)
+            .map(Constant::from_bytes)
+            .unwrap_or_else(|_| Constant::Str(value.to_string())),
+        NormalFieldType::Bool => Constant::Bool(value == "true"),
+        NormalFieldType::Double | NormalFieldType::Float => match value {
+            "inf" => Constant::Float(f64::INFINITY),
+            "-inf" => Constant::Float(f64::NEG_INFINITY),
+            "nan" => Constant::Float(f64::NAN),
+            _ => value
+                .parse()
+                .map(Constant::Float)
+                .unwrap_or_else(|_| Constant::Ident(value.to_string())),
+        },
+        _ if value.starts_with('-') => value
+            .parse()
+            .map(Constant::Int)
+            .unwrap_or_else(|_| Constant::Ident(value.to_string())),
+        _ => value
+            .parse()
+            .map(Constant::Uint)
+            .unwrap_or_else(|_| Constant::Ident(value.to_string())),
+    }
+}
+
+fn proto_option(parts: Vec<OptionNamePart>, value: Constant) -> ProtoOption {
+    ProtoOption {
+        name: OptionName { parts },
+        raw_value: value.to_string(),
+        value,
+        ..Default::default()
+    }
+}
+
+fn uninterpreted_from(opt: &UninterpretedOption) -> Result<ProtoOption> {
+    let parts = opt
+        .name
+        .iter()
+        .map(|part| OptionNamePart {
+            name: part.name_part.clone(),
+            is_extension: part.is_extension,
+        })
+        .collect();
+    let value = if let Some(v) = &opt.identifier_value {
+        match v.as_str() {
+            "true" => Constant::Bool(true),
+            "false" => Constant::Bool(false),
+            v => Constant::Ident(v.to_string()),
+        }
+    } else if let Some(v) = opt.positive_int_value {
+        Constant::Uint(v)
+    } else if let Some(v) = opt.negative_int_value {
+        Constant::Int(v)
+    } else if let Some(v) = opt.double_value {
+        Constant::Float(v)
+    } else if let Some(v) = &opt.string_value {
+        Constant::from_bytes(v.clone())
+    } else {
+        let text = opt.aggregate_value.clone().unwrap_or_default();
+        let value = parse_aggregate_text(&text).map_err(|e| Error::InvalidDescriptor {
+            message: format!("aggregate value {:?} does not parse: {}", text, e),
+        })?;
+        Constant::Aggregate(value)
+    };
+
+    Ok(proto_option(parts, value))
+}
+
+// Options of a descriptor, known fields first followed by the uninterpreted ones.
+#[derive(Default)]
+struct OptionList(Vec<ProtoOption>);
+
+impl OptionList {
+    fn push(&mut self, name: &str, value: Option<Constant>) {
+        if let Some(value) = value {
+            let part = OptionNamePart {
+                name: name.to_string(),
+                is_extension: false,
+            };
+            self.0.push(proto_option(vec![part], value));
+        }
+    }
+
+    fn push_bool(&mut self, name: &str, value: Option<bool>) {
+        self.push(name, value.map(Constant::Bool));
+    }
+
+    fn push_str(&mut self, name: &str, value: &Option<String>) {
+        self.push(name, value.clone().map(Constant::Str));
+    }
+
+    fn push_enum(&mut self, name: &str, value: Option<&str>) {
+        self.push(name, value.map(|v| Constant::Ident(v.to_string())));
+    }
+
+    fn finish(mut self, uninterpreted: &[UninterpretedOption]) -> Result<Vec<ProtoOption>> {
+        for opt in uninterpreted.iter() {
+            self.0.push(uninterpreted_from(opt)?);
+        }
+        Ok(self.0)
+    }
+}
+
+#[allow(deprecated)]
+fn file_options_from(options: Option<&FileOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_str("java_package", &o.java_package);
+    out.push_str("java_outer_classname", &o.java_outer_classname);
+    out.push_bool("java_multiple_files", o.java_multiple_files);
+    out.push_bool(
+        "java_generate_equals_and_hash",
+        o.java_generate_equals_and_hash,
+    );
+    out.push_bool("java_string_check_utf8", o.java_string_check_utf8);
+    out.push_enum(
+        "optimize_for",
+        o.optimize_for
+            .and_then(|v| OptimizeMode::try_from(v).ok())
+            .map(|v| v.as_str_name()),
+    );
+    out.push_str("go_package", &o.go_package);
+    out.push_bool("cc_generic_services", o.cc_generic_services);
+    out.push_bool("java_generic_services", o.java_generic_services);
+    out.push_bool("py_generic_services", o.py_generic_services);
+    out.push_bool("deprecated", o.deprecated);
+    out.push_bool("cc_enable_arenas", o.cc_enable_arenas);
+    out.push_str("objc_class_prefix", &o.objc_class_prefix);
+    out.push_str("csharp_namespace", &o.csharp_namespace);
+    out.push_str("swift_prefix", &o.swift_prefix);
+    out.push_str("php_class_prefix", &o.php_class_prefix);
+    out.push_str("php_namespace", &o.php_namespace);
+    out.push_str("php_metadata_namespace", &o.php_metadata_namespace);
+    out.push_str("ruby_package", &o.ruby_package);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn message_options_from(options: Option<&MessageOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_bool("message_set_wire_format", o.message_set_wire_format);
+    out.push_bool(
+        "no_standard_descriptor_accessor",
+        o.no_standard_descriptor_accessor,
+    );
+    out.push_bool("deprecated", o.deprecated);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn field_options_from(options: Option<&FieldOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_enum(
+        "ctype",
+        o.ctype
+            .and_then(|v| CType::try_from(v).ok())
+            .map(|v| v.as_str_name()),
+    );
+    out.push_bool("packed", o.packed);
+    out.push_enum(
+        "jstype",
+        o.jstype
+            .and_then(|v| JsType::try_from(v).ok())
+            .map(|v| v.as_str_name()),
+    );
+    out.push_bool("lazy", o.lazy);
+    out.push_bool("deprecated", o.deprecated);
+    out.push_bool("weak", o.weak);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn enum_options_from(options: Option<&EnumOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_bool("allow_alias", o.allow_alias);
+    out.push_bool("deprecated", o.deprecated);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn enum_value_options_from(options: Option<&EnumValueOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_bool("deprecated", o.deprecated);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn service_options_from(options: Option<&ServiceOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_bool("deprecated", o.deprecated);
+
+    out.finish(&o.uninterpreted_option)
+}
+
+fn method_options_from(options: Option<&MethodOptions>) -> Result<Vec<ProtoOption>> {
+    let o = match options {
+        Some(o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let mut out: OptionList = Default::default();
+
+    out.push_bool("deprecated", o.deprecated);
+    out.push_enum(
+        "idempotency_level",
+        o.idempotency_level
+            .and_then(|v| IdempotencyLevel::try_from(v).ok())
+            .map(|v| v.as_str_name()),
+    );
+
+    out.finish(&o.uninterpreted_option)
+}
+
+struct Importer<'a> {
+    syntax: Syntax,
+    locations: HashMap<&'a [i32], &'a Location>,
+}
+
+impl Importer<'_> {
+    // Adds `field` to the oneof declared at `index`, which starts where its first member is.
+    fn push_oneof_field(
+        &self,
+        out: &mut Message,
+        oneofs: &mut HashMap<i32, usize>,
+        msg: &DescriptorProto,
+        path: &[i32],
+        index: i32,
+        field: OneofField,
+    ) -> Result<()> {
+        let pos = match oneofs.get(&index) {
+            Some(pos) => *pos,
+            None => {
+                let decl = msg.oneof_decl.get(index as usize);
+                let (span, comments) = self.locate(&[path, &[MESSAGE_ONEOF_DECL, index]].concat());
+                out.fields.push(MessageField::Oneof(OneofDefine {
+                    name: decl.map(|o| o.name().to_string()).unwrap_or_default(),
+                    options: decl
+                        .and_then(|o| o.options.as_ref())
+                        .iter()
+                        .flat_map(|o| o.uninterpreted_option.iter().map(uninterpreted_from))
+                        .collect::<Result<_>>()?,
+                    span,
+                    comments,
+                    ..Default::default()
+                }));
+                oneofs.insert(index, out.fields.len() - 1);
+                out.fields.len() - 1
+            }
+        };
+        if let MessageField::Oneof(o) = &mut out.fields[pos] {
+            o.fields.push(field);
+        }
+        Ok(())
+    }
+
+    fn locate(&self, path: &[i32]) -> (Span, Comments) {
+        let location = match self.locations.get(path) {
+            Some(location) => location,
+            None => return Default::default(),
+        };
+        let span = match location.span.as_slice() {
+            [line, start, end] => Span {
+                start: (*line as usize + 1, *start as usize + 1),
+                end: (*line as usize + 1, *end as usize + 1),
+                ..Default::default()
+            },
+            [start_line, start, end_line, end] => Span {
+                start: (*start_line as usize + 1, *start as usize + 1),
+                end: (*end_line as usize + 1, *end as usize + 1),
+                ..Default::default()
+            },
+            _ => Default::default(),
+        };
+        let comments = Comments {
+            leading: location.leading_comments.clone(),
+            trailing: location.trailing_comments.clone(),
+            detached: location.leading_detached_comments.clone(),
+        };
+
+        (span, comments)
+    }
+
+    fn field(&self, f: &FieldDescriptorProto, path: &[i32]) -> Result<NormalField> {
+        let (span, comments) = self.locate(path);
+        let field_type =
+            field_type_from(f.r#type.and_then(|t| Type::try_from(t).ok()), f.type_name());
+        let label = match f.label() {
+            DescriptorLabel::Repeated => Label::Repeated,
+            DescriptorLabel::Required => Label::Required,
+            DescriptorLabel::Optional if self.syntax == Syntax::Proto2 || f.proto3_optional() => {
+                Label::Optional
+            }
+            DescriptorLabel::Optional => Label::Implicit,
+        };
+        let mut options = field_options_from(f.options.as_ref())?;
+        if let Some(value) = &f.default_value {
+            let value = default_value_from(&field_type, value);
+            options.insert(0, proto_option(simple_part("default"), value));
+        }
+        if let Some(value) = &f.json_name {
+            if *value != json_name(f.name()) {
+                let value = Constant::Str(value.clone());
+                options.push(proto_option(simple_part("json_name"), value));
+            }
+        }
+
+        Ok(NormalField {
+            label,
+            field_type,
+            name: f.name().to_string(),
+            number: f.number() as u32,
+            options,
+            proto3_optional: f.proto3_optional(),
+            span,
+            comments,
+            ..Default::default()
+        })
+    }
+
+    fn extensions(&self, fields: &[FieldDescriptorProto], path: &[i32]) -> Result<Vec<Extend>> {
+        let mut out: Vec<Extend> = Vec::new();
+
+        for (i, f) in fields.iter().enumerate() {
+            let field = self.field(f, &[path, &[i as i32]].concat())?;
+            match out.last_mut() {
+                Some(extend) if extend.extendee == f.extendee() => extend.fields.push(field),
+                _ => out.push(Extend {
+                    extendee: f.extendee().to_string(),
+                    fields: vec![field],
+                    span: self.locate(path).0,
+                }),
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn enumeration(&self, e: &EnumDescriptorProto, path: &[i32]) -> Result<Enum> {
+        let (span, comments) = self.locate(path);
+
+        Ok(Enum {
+            name: e.name().to_string(),
+            options: enum_options_from(e.options.as_ref())?,
+            fields: e
+                .value
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    let (span, comments) = self.locate(&[path, &[ENUM_VALUE, i as i32]].concat());
+                    Ok(EnumField {
+                        name: v.name().to_string(),
+                        value: v.number(),
+                        options: enum_value_options_from(v.options.as_ref())?,
+                        span,
+                        comments,
+                        ..Default::default()
+                    })
+                })
+                .collect::<Result<_>>()?,
+            reserved_ranges: e
+                .reserved_range
+                .iter()
+                .map(|r| ReservedRange {
+                    start: r.start(),
+                    end: r.end(),
+                })
+                .collect(),
+            reserved_names: e.reserved_name.clone(),
+            span,
+            comments,
+            ..Default::default()
+        })
+    }
+
+    fn message(&self, msg: &DescriptorProto, scope: &str, path: &[i32]) -> Result<Message> {
+        let (span, comments) = self.locate(path);
+        let scope = join(scope, msg.name());
+        let mut out = Message {
+            name: msg.name().to_string(),
+            options: message_options_from(msg.options.as_ref())?,
+            extension_ranges: msg
+                .extension_range
+                .iter()
+                .map(|r| {
+                    Ok(ExtensionRange {
+                        start: r.start(),
+                        end: inclusive_end(r.end())?,
+                        options: r
+                            .options
+                            .iter()
+                            .flat_map(|o| o.uninterpreted_option.iter().map(uninterpreted_from))
+                            .collect::<Result<_>>()?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            reserved_ranges: msg
+                .reserved_range
+                .iter()
+                .map(|r| {
+                    Ok(ReservedRange {
+                        start: r.start(),
+                        end: inclusive_end(r.end())?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            reserved_names: msg.reserved_name.clone(),
+            span,
+            comments,
+            ..Default::default()
+        };
+
+        // Map entries and group bodies are folded back into the fields using them.
+        let mut consumed = vec![false; msg.nested_type.len()];
+        let mut oneofs: HashMap<i32, usize> = HashMap::new();
+        for (i, f) in msg.field.iter().enumerate() {
+            let field_path = [path, &[MESSAGE_FIELD, i as i32]].concat();
+            let nested = msg
+                .nested_type
+                .iter()
+                .position(|m| f.type_name() == format!(".{}.{}", scope, m.name()));
+            let oneof = f.oneof_index.filter(|_| !f.proto3_optional());
+
+            match (f.r#type(), nested) {
+                (Type::Group, Some(k)) => {
+                    consumed[k] = true;
+                    let nested_path = [path, &[MESSAGE_NESTED_TYPE, k as i32]].concat();
+                    let field = self.field(f, &field_path)?;
+                    let message =
+                        Box::new(self.message(&msg.nested_type[k], &scope, &nested_path)?);
+                    match oneof {
+                        Some(index) => {
+                            let field = OneofField {
+                                name: field.name,
+                                field_type: field.field_type,
+                                number: field.number,
+                                options: field.options,
+                                group: Some(message),
+                                span: field.span,
+                                comments: field.comments,
+                                ..Default::default()
+                            };
+                            self.push_oneof_field(&mut out, &mut oneofs, msg, path, index, field)?;
+                        }
+                        None => out.fields.push(MessageField::Group(Group {
+                            label: field.label,
+                            name: msg.nested_type[k].name().to_string(),
+                            number: field.number,
+                            options: field.options,
+                            message,
+                            span: field.span,
+                            comments: field.comments,
+                            ..Default::default()
+                        })),
+                    }
+                }
+                (Type::Message, Some(k))
+                    if msg.nested_type[k]
+                        .options
+                        .as_ref()
+                        .is_some_and(|o| o.map_entry()) =>
+                {
+                    consumed[k] = true;
+                    let entry = &msg.nested_type[k];
+                    let entry_type = |number: i32| {
+                        entry
+                            .field
+                            .iter()
+                            .find(|f| f.number() == number)
+                            .map(|f| {
+                                field_type_from(
+                                    f.r#type.and_then(|t| Type::try_from(t).ok()),
+                                    f.type_name(),
+                                )
+                            })
+                            .unwrap_or_default()
+                    };
+                    let field = self.field(f, &field_path)?;
+                    out.fields.push(MessageField::Map(MapField {
+                        name: field.name,
+                        key_type: map_key_type_from(&entry_type(1)),
+                        value_type: entry_type(2),
+                        number: field.number,
+                        options: field.options,
+                        span: field.span,
+                        comments: field.comments,
+                        ..Default::default()
+                    }));
+                }
+                _ => match oneof {
+                    Some(index) => {
+                        let field = self.field(f, &field_path)?;
+                        let field = OneofField {
+                            name: field.name,
+                            field_type: field.field_type,
+                            number: field.number,
+                            options: field.options,
+                            span: field.span,
+                            comments: field.comments,
+                            ..Default::default()
+                        };
+                        self.push_oneof_field(&mut out, &mut oneofs, msg, path, index, field)?;
+                    }
+                    None => {
+                        let mut field = self.field(f, &field_path)?;
+                        if field.proto3_optional {
+                            field.synthetic_oneof = f
+                                .oneof_index
+                                .and_then(|i| msg.oneof_decl.get(i as usize))
+                                .map(|o| o.name().to_string());
+                        }
+                        out.fields.push(MessageField::Normal(field));
+                    }
+                },
+            }
+        }
+
+        for (k, m) in msg.nested_type.iter().enumerate() {
+            if !consumed[k] {
+                let path = [path, &[MESSAGE_NESTED_TYPE, k as i32]].concat();
+                out.inner_messages.push(self.message(m, &scope, &path)?);
+            }
+        }
+        for (i, e) in msg.enum_type.iter().enumerate() {
+            let path = [path, &[MESSAGE_ENUM_TYPE, i as i32]].concat();
+            out.inner_enums.push(self.enumeration(e, &path)?);
+        }
+        out.extends = self.extensions(&msg.extension, &[path, &[MESSAGE_EXTENSION]].concat())?;
+
+        Ok(out)
+    }
+
+    fn service(&self, s: &ServiceDescriptorProto, path: &[i32]) -> Result<Service> {
+        let (span, comments) = self.locate(path);
+
+        Ok(Service {
+            name: s.name().to_string(),
+            options: service_options_from(s.options.as_ref())?,
+            rpcs: s
+                .method
+                .iter()
+                .enumerate()
+                .map(|(i, m)| {
+                    let (span, comments) =
+                        self.locate(&[path, &[SERVICE_METHOD, i as i32]].concat());
+                    Ok(RPC {
+                        name: m.name().to_string(),
+                        request: m.input_type().to_string(),
+                        response: m.output_type().to_string(),
+                        client_streaming: m.client_streaming(),
+                        server_streaming: m.server_streaming(),
+                        options: method_options_from(m.options.as_ref())?,
+                        span,
+                        comments,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            span,
+            comments,
+        })
+    }
+}
+
+// Descriptor ranges are end-exclusive, model ranges inclusive.
+fn inclusive_end(end: i32) -> Result<i32> {
+    end.checked_sub(1).ok_or_else(|| Error::InvalidDescriptor {
+        message: format!("range end {} is out of range", end),
+    })
+}
+
+fn simple_part(name: &str) -> Vec<OptionNamePart> {
+    vec![OptionNamePart {
+        name: name.to_string(),
+        is_extension: false,
+    }]
+}
+
+/// Rebuilds a `ProtoFile` from its descriptor, type references stay fully qualified.
+pub fn proto_file_from_descriptor(file: &FileDescriptorProto) -> Result<ProtoFile> {
+    let syntax = match file.syntax() {
+        "" | "proto2" => Syntax::Proto2,
+        "proto3" => Syntax::Proto3,
+        // prost-types has no `edition` field, so which edition is lost in decoding
+        "editions" => {
+            return Err(Error::UnsupportedEdition {
+                edition: "editions".to_string(),
+                location: Default::default(),
+            })
+        }
+        syntax => {
+            return Err(Error::UnsupportedSyntax {
+                syntax: syntax.to_string(),
+                location: Default::default(),
+            })
+        }
+    };
+    let importer = Importer {
+        syntax,
+        locations: file
+            .source_code_info
+            .iter()
+            .flat_map(|info| info.location.iter())
+            .map(|l| (l.path.as_slice(), l))
+            .collect(),
+    };
+    let mut proto = ProtoFile {
+        filename: file.name().to_string(),
+        syntax,
+        package: file.package().to_string(),
+        package_span: importer.locate(&[FILE_PACKAGE]).0,
+        options: file_options_from(file.options.as_ref())?,
+        ..Default::default()
+    };
+
+    for (i, dependency) in file.dependency.iter().enumerate() {
+        let index = i as i32;
+        proto.import.push(Import {
+            import_type: if file.public_dependency.contains(&index) {
+                ImportType::Public
+            } else if file.weak_dependency.contains(&index) {
+                ImportType::Weak
+            } else {
+                ImportType::Normal
+            },
+            proto_file: dependency.clone(),
+            span: importer.locate(&[FILE_DEPENDENCY, index]).0,
+        });
+    }
+    for (i, m) in file.message_type.iter().enumerate() {
+        let m = importer.message(m, &proto.package, &[FILE_MESSAGE_TYPE, i as i32])?;
+        proto.messages.push(m);
+    }
+    for (i, e) in file.enum_type.iter().enumerate() {
+        let e = importer.enumeration(e, &[FILE_ENUM_TYPE, i as i32])?;
+        proto.enums.push(e);
+    }
+    for (i, s) in file.service.iter().enumerate() {
+        let s = importer.service(s, &[FILE_SERVICE, i as i32])?;
+        proto.services.push(s);
+    }
+    proto.extends = importer.extensions(&file.extension, &[FILE_EXTENSION])?;
+    let mut errors = Vec::new();
+    crate::features::resolve(&mut proto, &mut errors);
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e);
+    }
+
+    Ok(proto)
+}
+
+/// Rebuilds every file of `set`, which should list each file after its dependencies.
+pub fn file_set_from_descriptor(set: &FileDescriptorSet) -> Result<FileSet> {
+    let mut files: FileSet = Default::default();
+
+    for file in set.file.iter() {
+        files.insert(proto_file_from_descriptor(file)?);
+    }
+
+    Ok(files)
+}
+
+/// Decodes an encoded `FileDescriptorSet`, such as the output of `protoc -o`.
+pub fn decode_file_descriptor_set(bytes: &[u8]) -> Result<FileSet> {
+    let set = FileDescriptorSet::decode(bytes)?;
+
+    file_set_from_descriptor(&set)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_proto_text;
+    use crate::resolve::link;
+
+    fn descriptor(text: &str) -> FileDescriptorProto {
+        let mut proto = parse_proto_text(text).expect("parse proto text failed");
+        proto.filename = "test.proto".to_string();
+        let mut set: FileSet = Default::default();
+        set.insert(proto);
+        link(&mut set).expect("link failed");
+        file_descriptor_set(&set)
+            .expect("file descriptor set failed")
+            .file
+            .remove(0)
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(json_name("foo_bar_baz"), "fooBarBaz");
+        assert_eq!(json_name("_foo__bar"), "FooBar");
+        assert_eq!(map_entry_name("string_map"), "StringMapEntry");
+        assert_eq!(c_escape(b"a\n\x01\xff"), "a\\n\\001\\377");
+    }
+
+    #[test]
+    fn test_file_descriptor() {
+        let file = descriptor(
+            r#"syntax = "proto3";
+            package foo.bar;
+            import public "other.proto";
+            option java_package = "com.foo";
+            option (my_option).a = 5;
+
+            // A message.
+            message Outer {
+                optional int32 count = 1 [json_name = "total"];
+                map<string, Inner> inners = 2;
+                oneof choice {
+                    string name = 3;
+                    Kind kind = 4 [deprecated = true];
+                }
+                message Inner {}
+                enum Kind { KIND_UNSPECIFIED = 0; }
+                reserved 10 to 12;
+            }
+
+            service Svc {
+                rpc Call(stream Outer) returns (Outer.Inner);
+            }
+            "#,
+        );
+
+        assert_eq!(file.name(), "test.proto");
+        assert_eq!(file.package(), "foo.bar");
+        assert_eq!(file.syntax(), "proto3");
+        assert_eq!(file.dependency, vec!["other.proto"]);
+        assert_eq!(file.public_dependency, vec![0]);
+        let options = file.options.as_ref().expect("file options");
+        assert_eq!(options.java_package(), "com.foo");
+        assert_eq!(options.uninterpreted_option.len(), 1);
+        assert_eq!(
+            options.uninterpreted_option[0].name[0].name_part,
+            "my_option"
+        );
+        assert!(options.uninterpreted_option[0].name[0].is_extension);
+        assert_eq!(options.uninterpreted_option[0].positive_int_value, Some(5));
+
+        let outer = &file.message_type[0];
+        let names: Vec<_> = outer.field.iter().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["count", "inners", "name", "kind"]);
+        assert_eq!(outer.field[0].json_name(), "total");
+        assert_eq!(outer.field[0].oneof_index, Some(1));
+        assert_eq!(outer.field[0].proto3_optional, Some(true));
+        assert_eq!(outer.field[1].label(), DescriptorLabel::Repeated);
+        assert_eq!(outer.field[1].type_name(), ".foo.bar.Outer.InnersEntry");
+        assert_eq!(outer.field[3].r#type(), Type::Enum);
+        assert_eq!(outer.field[3].type_name(), ".foo.bar.Outer.Kind");
+        assert_eq!(outer.field[3].oneof_index, Some(0));
+        assert!(outer.field[3].options.as_ref().unwrap().deprecated());
+        let oneofs: Vec<_> = outer.oneof_decl.iter().map(|o| o.name()).collect();
+        assert_eq!(oneofs, vec!["choice", "_count"]);
+        let nested: Vec<_> = outer.nested_type.iter().map(|m| m.name()).collect();
+        assert_eq!(nested, vec!["InnersEntry", "Inner"]);
+        let entry = &outer.nested_type[0];
+        assert!(entry.options.as_ref().unwrap().map_entry());
+        assert_eq!(entry.field[1].type_name(), ".foo.bar.Outer.Inner");
+        assert_eq!(outer.reserved_range[0].end(), 13);
+
+        let method = &file.service[0].method[0];
+        assert_eq!(method.input_type(), ".foo.bar.Outer");
+        assert_eq!(method.output_type(), ".foo.bar.Outer.Inner");
+        assert_eq!(method.client_streaming, Some(true));
+        assert_eq!(method.server_streaming, None);
+
+        let locations = &file.source_code_info.as_ref().unwrap().location;
+        let outer_location = locations
+            .iter()
+            .find(|l| l.path == vec![4, 0])
+            .expect("message location");
+        assert_eq!(outer_location.span, vec![7, 12, 17, 13]);
+        assert_eq!(outer_location.leading_comments(), " A message.\n");
+        let kind = locations
+            .iter()
+            .find(|l| l.path == vec![4, 0, 2, 3])
+            .expect("field location");
+        assert_eq!(kind.span, vec![12, 20, 54]);
+
+        let mut proto = parse_proto_text(r#"edition = "2023"; message A { int32 a = 1; }"#)
+            .expect("parse proto text failed");
+        proto.filename = "edition.proto".to_string();
+        match file_descriptor(&proto) {
+            Err(Error::UnsupportedEdition { edition, .. }) => assert_eq!(edition, "2023"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut set: FileSet = Default::default();
+        set.insert(proto);
+        match file_descriptor_set(&set) {
+            Err(Error::FileError { filename, .. }) => assert_eq!(filename, "edition.proto"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_proto2_descriptor() {
+        let file = descriptor(
+            r#"syntax = "proto2";
+            message Foo {
+                optional bytes data = 1 [default = "\n\x01"];
+                optional double ratio = 2 [default = inf];
+                repeated group Result = 3 { required string url = 4; }
+                optional float scale = 5 [default = 1e20];
+                optional double third = 6 [default = 0.3333333333333333];
+                optional double tiny = 8 [default = -0.00001];
+                oneof choice { string label = 7 [default = "x"]; }
+                extensions 100 to max;
+            }
+            extend Foo { optional int32 bar = 100; }
+            "#,
+        );
+
+        assert_eq!(file.syntax, None);
+        let foo = &file.message_type[0];
+        assert_eq!(foo.field[0].default_value(), "\\n\\001");
+        assert_eq!(foo.field[1].default_value(), "inf");
+        assert_eq!(foo.field[2].name(), "result");
+        assert_eq!(foo.field[2].r#type(), Type::Group);
+        assert_eq!(foo.field[2].type_name(), ".Foo.Result");
+        assert_eq!(foo.field[3].default_value(), "1e+20");
+        assert_eq!(foo.field[4].default_value(), "0.33333333333333331");
+        assert_eq!(foo.field[5].default_value(), "-1e-05");
+        assert_eq!(foo.field[6].default_value(), "x");
+        assert_eq!(
+            foo.nested_type[0].field[0].label(),
+            DescriptorLabel::Required
+        );
+        assert_eq!(foo.extension_range[0].end(), MAX_FIELD_NUMBER + 1);
+        assert_eq!(file.extension[0].extendee(), ".Foo");
+        assert_eq!(file.extension[0].json_name(), "bar");
+    }
+
+    #[test]
+    fn test_decode_file_descriptor_set() {
+        let mut proto = parse_proto_text(
+            r#"syntax = "proto2";
+            package foo;
+            option optimize_for = SPEED;
+
+            // Leading.
+            message Foo {
+                optional bytes data = 1 [default = "\n\x01"];
+                map<string, Foo> children = 2;
+                repeated group Result = 3 { required string url = 4; }
+                oneof choice {
+                    option (tag) = "c";
+                    int32 id = 5 [json_name = "ID"];
+                    Kind kind = 6;
+                    group Pick = 7 { optional int32 id = 8; }
+                }
+                enum Kind { A = 0; B = 1 [(tag) = "b"]; }
+                extensions 100 to max;
+                reserved "gone";
+            }
+            extend Foo { optional int32 bar = 100; }
+            service Svc {
+                rpc Get(Foo) returns (stream Foo) {
+                    option deprecated = true;
+                    option (http) = { get: "/v1/{id}" body: "*" };
+                }
+            }
+            "#,
+        )
+        .expect("parse proto text failed");
+        proto.filename = "foo.proto".to_string();
+        let mut set: FileSet = Default::default();
+        set.insert(proto);
+        link(&mut set).expect("link failed");
+        let descriptors = file_descriptor_set(&set).expect("file descriptor set failed");
+
+        let bytes = prost::Message::encode_to_vec(&descriptors);
+        let decoded = decode_file_descriptor_set(&bytes).expect("decode failed");
+        assert_eq!(
+            file_descriptor_set(&decoded).expect("file descriptor set failed"),
+            descriptors
+        );
+
+        let proto = decoded.get("foo.proto").expect("file not found");
+        assert_eq!(proto.package, "foo");
+        assert_eq!(proto.syntax, Syntax::Proto2);
+        assert!(proto.options[0].name == "optimize_for");
+        assert_eq!(proto.options[0].value, Constant::Ident("SPEED".to_string()));
+
+        let foo = &proto.messages[0];
+        assert_eq!(foo.comments.leading.as_deref(), Some(" Leading.\n"));
+        assert!(foo.inner_messages.is_empty());
+        assert_eq!(foo.inner_enums[0].fields[1].options[0].raw_value, "\"b\"");
+        match &foo.fields[0] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.label, Label::Optional);
+                assert_eq!(
+                    f.default_value(),
+                    Some(&Constant::Str("\n\u{1}".to_string()))
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &foo.fields[1] {
+            MessageField::Map(f) => {
+                assert_eq!(f.name, "children");
+                assert_eq!(
+                    f.value_type,
+                    NormalFieldType::Message(".foo.Foo".to_string())
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &foo.fields[2] {
+            MessageField::Group(g) => {
+                assert_eq!(g.name, "Result");
+                assert_eq!(g.message.fields.len(), 1);
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &foo.fields[3] {
+            MessageField::Oneof(o) => {
+                assert_eq!(o.name, "choice");
+                assert_eq!(o.options[0].raw_value, "\"c\"");
+                assert_eq!(o.fields.len(), 3);
+                assert_eq!(o.fields[2].name, "pick");
+                assert_eq!(
+                    o.fields[2].group.as_ref().map(|m| m.name.as_str()),
+                    Some("Pick")
+                );
+                assert_eq!(
+                    o.fields[0].options[0].value,
+                    Constant::Str("ID".to_string())
+                );
+                assert_eq!(
+                    o.fields[1].field_type,
+                    NormalFieldType::Enum(".foo.Foo.Kind".to_string())
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        assert_eq!(foo.extension_ranges[0].end, MAX_FIELD_NUMBER);
+        assert_eq!(proto.extends[0].extendee, ".foo.Foo");
+        let rpc = &proto.services[0].rpcs[0];
+        assert!(rpc.server_streaming && !rpc.client_streaming);
+        assert_eq!(rpc.options[0].value, Constant::Bool(true));
+        let method = &descriptors.file[0].service[0].method[0];
+        assert_eq!(
+            method.options.as_ref().unwrap().uninterpreted_option[0].aggregate_value(),
+            "get: \"/v1/{id}\" body: \"*\""
+        );
+        match &rpc.options[1].value {
+            Constant::Aggregate(v) => assert_eq!(
+                v.get("get"),
+                Some(&AggregateFieldValue::Scalar(Constant::Str(
+                    "/v1/{id}".to_string()
+                )))
+            ),
+            v => panic!("unexpected value {:?}", v),
+        }
+
+        let mut malformed = descriptors.file[0].clone();
+        let options = malformed.service[0].method[0].options.as_mut().unwrap();
+        options.uninterpreted_option[0].aggregate_value = Some("get: \"a\" body:".to_string());
+        match proto_file_from_descriptor(&malformed) {
+            Err(Error::InvalidDescriptor { .. }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let mut malformed = descriptors.file[0].clone();
+        malformed.message_type[0].extension_range[0].end = Some(i32::MIN);
+        match proto_file_from_descriptor(&malformed) {
+            Err(Error::InvalidDescriptor { .. }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        malformed.syntax = Some("editions".to_string());
+        match proto_file_from_descriptor(&malformed) {
+            Err(Error::UnsupportedEdition { .. }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use pest::error::InputLocation;
+
+use crate::model::Span;
+use crate::parser::Rule;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Empty when the source is not a named file.
+    pub filename: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            filename: String::new(),
+            span,
+        }
+    }
+
+    pub fn filename(mut self, filename: impl Into<String>) -> Diagnostic {
+        self.filename = filename.into();
+        self
+    }
+
+    /// Renders the diagnostic followed by the source line it points at, underlined.
+    pub fn render(&self, source: &str) -> String {
+        let (line, col) = self.span.start;
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        let gutter = " ".repeat(line.to_string().len());
+        if self.filename.is_empty() {
+            out.push_str(&format!("{}--> {}:{}\n", gutter, line, col));
+        } else {
+            out.push_str(&format!(
+                "{}--> {}:{}:{}\n",
+                gutter, self.filename, line, col
+            ));
+        }
+
+        let text = match source.lines().nth(line.saturating_sub(1)) {
+            Some(text) => text,
+            None => return out,
+        };
+        let start = col.saturating_sub(1);
+        let len = text.chars().count();
+        let end = if self.span.end.0 == line {
+            self.span.end.1.saturating_sub(1).min(len)
+        } else {
+            len
+        };
+        // keep tabs so the underline lines up with the source
+        let indent: String = text
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line, text));
+        out.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            indent,
+            "^".repeat(end.saturating_sub(start).max(1))
+        ));
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.filename.is_empty() {
+            write!(f, "{}:", self.filename)?;
+        }
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.span.start.0, self.span.start.1, self.severity, self.message
+        )
+    }
+}
+
+// pest renders expected tokens as the literal text, or `a..z` for a char range.
+fn describe(token: String) -> Option<String> {
+    let chars: Vec<char> = token.chars().collect();
+    Some(match chars.as_slice() {
+        // trivia is allowed everywhere, never worth naming
+        [' '] | ['\t'] | ['\n'] | ['\r', '\n'] | ['/', '/'] | ['/', '*'] => return None,
+        ['_'] => "identifier".to_string(),
+        ['"'] | ['\''] => "string".to_string(),
+        ['0'] => "number".to_string(),
+        [start, '.', '.', _] if start.is_ascii_alphabetic() => "identifier".to_string(),
+        [start, '.', '.', _] if start.is_ascii_digit() => "number".to_string(),
+        _ if token == "BUILTIN_RULE" => return None,
+        _ => format!("\"{}\"", token),
+    })
+}
+
+// Moves past whitespace and comments, so errors point at the next real token.
+fn skip_trivia(text: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &text[pos..];
+        let trimmed = rest.trim_start();
+        let skipped = if trimmed.starts_with("//") {
+            trimmed.find('\n').unwrap_or(trimmed.len())
+        } else if let Some(comment) = trimmed.strip_prefix("/*") {
+            comment.find("*/").map_or(trimmed.len(), |end| end + 4)
+        } else {
+            0
+        };
+        let next = pos + (rest.len() - trimmed.len()) + skipped;
+        if next == pos {
+            return pos;
+        }
+        pos = next;
+    }
+}
+
+// The token starting at `pos`, as a user would name it.
+fn found(text: &str, pos: usize) -> String {
+    let rest = &text[pos..];
+    match rest.chars().next() {
+        None => "end of file".to_string(),
+        Some(c) if c.is_alphanumeric() || c == '_' => {
+            let word: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            format!("\"{}\"", word)
+        }
+        Some(c) => format!("\"{}\"", c.escape_default()),
+    }
+}
+
+/// Describes a grammar failure in protobuf terms, with the location of the offending token.
+///
+/// `error` comes from matching `text[offset..]`.
+pub(crate) fn grammar_failure(
+    error: &pest::error::Error<Rule>,
+    text: &str,
+    offset: usize,
+) -> (String, Span) {
+    let (pos, mut expected) = match error.parse_attempts() {
+        Some(attempts) => (
+            attempts.max_position,
+            attempts
+                .expected_tokens()
+                .into_iter()
+                .filter_map(|t| describe(t.to_string()))
+                .collect::<Vec<_>>(),
+        ),
+        None => match error.location {
+            InputLocation::Pos(pos) | InputLocation::Span((pos, _)) => (pos, Vec::new()),
+        },
+    };
+    // keywords are identifiers as far as the reader is concerned
+    if expected.iter().any(|t| t == "identifier") {
+        expected.retain(|t| !t.starts_with('"') || !t[1..].starts_with(char::is_alphabetic));
+    }
+    let mut seen = HashSet::new();
+    expected.retain(|t| seen.insert(t.clone()));
+
+    let pos = skip_trivia(text, (offset + pos).min(text.len()));
+    let end = text[pos..]
+        .chars()
+        .next()
+        .map_or(pos, |c| pos + c.len_utf8());
+    let span = pest::Span::new(text, pos, end)
+        .map(Span::from)
+        .unwrap_or_default();
+
+    let message = match expected.split_last() {
+        None => format!("Unexpected {}.", found(text, pos)),
+        Some((last, [])) => format!("Expected {}, found {}.", last, found(text, pos)),
+        Some((last, rest)) => format!(
+            "Expected {} or {}, found {}.",
+            rest.join(", "),
+            last,
+            found(text, pos)
+        ),
+    };
+
+    (message, span)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+    use crate::parser::parse_proto_text;
+
+    fn syntax_error(text: &str) -> Diagnostic {
+        match parse_proto_text(text) {
+            Err(e) => e.diagnostic().expect("error has no location"),
+            Ok(_) => panic!("parse should fail"),
+        }
+    }
+
+    #[test]
+    fn test_grammar_diagnostics() {
+        let d = syntax_error("syntax = \"proto3\";\nmessage A {\n  int32 a = 1\n}\n");
+        assert_eq!(d.message, "Expected \";\" or \"[\", found \"}\".");
+        assert_eq!(d.span.start, (4, 1));
+
+        let d = syntax_error("syntax = \"proto3\";\nmessage A {\n  int32 a = ;\n}\n");
+        assert_eq!(d.message, "Expected number, found \";\".");
+        assert_eq!(
+            d.render("syntax = \"proto3\";\nmessage A {\n  int32 a = ;\n}\n"),
+            "error: Expected number, found \";\".\n --> 3:13\n  |\n3 |   int32 a = ;\n  |             ^\n"
+        );
+
+        let d = syntax_error("syntax = \"proto3\";\nmessage {}\n");
+        assert_eq!(d.message, "Expected identifier, found \"{\".");
+
+        let d = syntax_error("syntax = \"proto3\";\nmessage A {\n  int32 a = 1;\n");
+        assert_eq!(
+            d.message,
+            "Expected \".\", \";\", identifier or \"}\", found end of file."
+        );
+
+        match parse_proto_text("syntax = \"proto3\";\nfoo bar;") {
+            Err(e @ Error::GrammarError { .. }) => assert_eq!(
+                e.to_string(),
+                "2:1: Expected \";\", \"enum\", \"extend\", \"import\", \"message\", \"option\", \"package\" or \"service\", found \"foo\"."
+            ),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "message A {\n\tint32 a = 0x100000000;\n}\n";
+        let d = match parse_proto_text(source) {
+            Err(e) => e.diagnostic().unwrap().filename("a.proto"),
+            Ok(_) => panic!("parse should fail"),
+        };
+
+        assert_eq!(d.to_string(), "a.proto:2:12: error: Integer out of range.");
+        assert_eq!(
+            d.render(source),
+            "error: Integer out of range.\n --> a.proto:2:12\n  |\n2 | \tint32 a = 0x100000000;\n  | \t          ^^^^^^^^^^^\n"
+        );
+    }
+}
@@ -1,23 +1,181 @@
 use snafu::Snafu;
 
+use crate::diagnostic::{grammar_failure, Diagnostic};
+use crate::model::Span;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("io error: {:?}", source))]
     IoError { source: std::io::Error },
 
-    #[snafu(display("grammar error"))]
-    GrammarError {
-        source: pest::error::Error<crate::parser::Rule>,
-    },
+    #[snafu(display("{}:{}: {}", location.start.0, location.start.1, message))]
+    GrammarError { message: String, location: Span },
 
     #[snafu(display("parse int error: {:?}", source))]
     ParseIntError { source: std::num::ParseIntError },
 
-    #[snafu(display("unexpect token: {:?}", token))]
-    UnexpectToken { token: String, location: String },
+    #[snafu(display(
+        "{}:{}: parse float error: {:?}",
+        location.start.0,
+        location.start.1,
+        source
+    ))]
+    ParseFloatError {
+        source: std::num::ParseFloatError,
+        location: Span,
+    },
+
+    #[snafu(display("decode error: {}", source))]
+    DecodeError { source: prost::DecodeError },
+
+    #[snafu(display("invalid descriptor: {}", message))]
+    InvalidDescriptor { message: String },
+
+    #[snafu(display(
+        "{}:{}: integer out of range: {}",
+        location.start.0,
+        location.start.1,
+        literal
+    ))]
+    IntegerOutOfRange { literal: String, location: Span },
+
+    #[snafu(display(
+        "{}:{}: string literal is not valid UTF-8: {}",
+        location.start.0,
+        location.start.1,
+        literal
+    ))]
+    InvalidUtf8 { literal: String, location: Span },
+
+    #[snafu(display(
+        "{}:{}: invalid escape: {:?}",
+        location.start.0,
+        location.start.1,
+        escape
+    ))]
+    InvalidEscape { escape: String, location: Span },
+
+    #[snafu(display("unsupported syntax: {:?}", syntax))]
+    UnsupportedSyntax { syntax: String, location: Span },
+
+    #[snafu(display("unsupported edition: {:?}", edition))]
+    UnsupportedEdition { edition: String, location: Span },
+
+    #[snafu(display(
+        "{}:{}: invalid value {:?} for feature {:?}",
+        location.start.0,
+        location.start.1,
+        value,
+        feature
+    ))]
+    InvalidFeatureValue {
+        feature: String,
+        value: String,
+        location: Span,
+    },
 
-    #[snafu(display("token not found: {:?}", token))]
-    TokenNotFound { token: String },
+    #[snafu(display("{}:{}: {}", location.start.0, location.start.1, message))]
+    InvalidLabel { message: String, location: Span },
+
+    #[snafu(display("file {:?} not found in include paths", filename))]
+    FileNotFound { filename: String },
+
+    #[snafu(display(
+        "{}:{}:{}: import {:?} not found",
+        importer,
+        location.start.0,
+        location.start.1,
+        import
+    ))]
+    ImportNotFound {
+        import: String,
+        importer: String,
+        location: Span,
+    },
+
+    #[snafu(display("import cycle: {}", cycle.join(" -> ")))]
+    ImportCycle { cycle: Vec<String> },
+
+    #[snafu(display("{}: {}", filename, source))]
+    FileError {
+        filename: String,
+        source: Box<Error>,
+    },
+
+    #[snafu(display("{:?} is already defined in file {:?}", name, defined_in))]
+    DuplicateSymbol {
+        name: String,
+        defined_in: String,
+        filename: String,
+        location: Span,
+    },
+
+    #[snafu(display("{:?} is not defined", name))]
+    UnknownType { name: String, location: Span },
+
+    #[snafu(display(
+        "{:?} is resolved to {:?}, which is not defined. The innermost scope is searched first in name resolution. Consider using a leading '.'(i.e., \".{}\") to start from the outermost scope",
+        name,
+        resolved,
+        name
+    ))]
+    AmbiguousType {
+        name: String,
+        resolved: String,
+        location: Span,
+    },
+
+    #[snafu(display(
+        "{:?} seems to be defined in {:?}, which is not imported by {:?}. To use it here, please add the necessary import",
+        name,
+        defined_in,
+        importer
+    ))]
+    TypeNotImported {
+        name: String,
+        defined_in: String,
+        importer: String,
+        location: Span,
+    },
+
+    #[snafu(display("{:?} is not a type", name))]
+    NotAType { name: String, location: Span },
+
+    #[snafu(display("{:?} is not a message type", name))]
+    NotAMessageType { name: String, location: Span },
+
+    #[snafu(display("overlapping edits at bytes {}..{}", start, end))]
+    OverlappingEdits { start: usize, end: usize },
+
+    #[snafu(display(
+        "edit at bytes {}..{} is out of bounds or splits a character",
+        start,
+        end
+    ))]
+    InvalidEditRange { start: usize, end: usize },
+
+    #[snafu(display("cannot edit node at bytes {}..{}: {}", start, end, message))]
+    InvalidEditTarget {
+        start: usize,
+        end: usize,
+        message: String,
+    },
+
+    #[snafu(display(
+        "{}:{}: unexpect token: {:?}",
+        location.start.0,
+        location.start.1,
+        token
+    ))]
+    UnexpectToken { token: String, location: Span },
+
+    #[snafu(display(
+        "{}:{}: token not found: {:?}",
+        location.start.0,
+        location.start.1,
+        token
+    ))]
+    TokenNotFound { token: String, location: Span },
 }
 
 impl From<std::io::Error> for Error {
@@ -32,23 +190,122 @@ impl From<std::num::ParseIntError> for Error {
     }
 }
 
-impl From<pest::error::Error<crate::parser::Rule>> for Error {
-    fn from(e: pest::error::Error<crate::parser::Rule>) -> Error {
-        Error::GrammarError { source: e }
+impl From<prost::DecodeError> for Error {
+    fn from(e: prost::DecodeError) -> Error {
+        Error::DecodeError { source: e }
+    }
+}
+
+impl Error {
+    /// The located form of this error, for errors that point into a source file.
+    pub fn diagnostic(&self) -> Option<Diagnostic> {
+        let (message, location) = match self {
+            Error::FileError { filename, source } => {
+                return source.diagnostic().map(|d| d.filename(filename.as_str()))
+            }
+            Error::ImportNotFound {
+                import,
+                importer,
+                location,
+            } => {
+                let message = format!("Import \"{}\" was not found or had errors.", import);
+                return Some(
+                    Diagnostic::error(message, location.clone()).filename(importer.as_str()),
+                );
+            }
+            Error::DuplicateSymbol {
+                name,
+                defined_in,
+                filename,
+                location,
+            } => {
+                let message = format!(
+                    "\"{}\" is already defined in file \"{}\".",
+                    name, defined_in
+                );
+                return Some(
+                    Diagnostic::error(message, location.clone()).filename(filename.as_str()),
+                );
+            }
+            Error::GrammarError { message, location }
+            | Error::InvalidLabel { message, location } => (message.clone(), location),
+            Error::IntegerOutOfRange { location, .. } => {
+                ("Integer out of range.".to_string(), location)
+            }
+            Error::InvalidUtf8 { location, .. } => {
+                ("String literal is not valid UTF-8.".to_string(), location)
+            }
+            Error::UnsupportedSyntax { syntax, location } => (
+                format!("Unrecognized syntax identifier \"{}\".  This parser only recognizes \"proto2\" and \"proto3\".", syntax),
+                location,
+            ),
+            Error::UnsupportedEdition { location, .. } => (self.to_string(), location),
+            Error::UnexpectToken { token, location } => {
+                (format!("Unexpected \"{}\".", token), location)
+            }
+            Error::TokenNotFound { token, location } => {
+                (format!("Expected {}.", token), location)
+            }
+            Error::ParseFloatError { location, .. } => {
+                ("Invalid floating point number.".to_string(), location)
+            }
+            Error::InvalidEscape { escape, location } => {
+                (format!("Invalid escape sequence \"{}\".", escape), location)
+            }
+            Error::InvalidFeatureValue {
+                feature,
+                value,
+                location,
+            } => (
+                format!("Invalid value \"{}\" for feature \"{}\".", value, feature),
+                location,
+            ),
+            Error::UnknownType { location, .. }
+            | Error::AmbiguousType { location, .. }
+            | Error::TypeNotImported { location, .. }
+            | Error::NotAType { location, .. }
+            | Error::NotAMessageType { location, .. } => (self.to_string(), location),
+            _ => return None,
+        };
+
+        Some(Diagnostic::error(message, location.clone()))
     }
 }
 
+pub(crate) fn grammar_error(
+    error: pest::error::Error<crate::parser::Rule>,
+    text: &str,
+    offset: usize,
+) -> Error {
+    let (message, location) = grammar_failure(&error, text, offset);
+    Error::GrammarError { message, location }
+}
+
 pub(crate) fn unexpect_token(pair: pest::iterators::Pair<'_, crate::parser::Rule>) -> Error {
-    let span = pair.as_span();
     Error::UnexpectToken {
-        token:  span.as_str().to_string(),
-        location: format!("{:?}", span.start_pos().line_col()),
+        token: pair.as_str().to_string(),
+        location: pair.as_span().into(),
+    }
+}
+
+pub(crate) fn integer_out_of_range(pair: pest::iterators::Pair<'_, crate::parser::Rule>) -> Error {
+    Error::IntegerOutOfRange {
+        literal: pair.as_str().to_string(),
+        location: pair.as_span().into(),
+    }
+}
+
+pub(crate) fn invalid_escape(escape: impl ToString, location: pest::Span<'_>) -> Error {
+    Error::InvalidEscape {
+        escape: escape.to_string(),
+        location: location.into(),
     }
 }
 
-pub(crate) fn token_not_found(token: impl ToString) -> Error {
+pub(crate) fn token_not_found(token: impl ToString, location: pest::Span<'_>) -> Error {
     Error::TokenNotFound {
         token: token.to_string(),
+        location: location.into(),
     }
 }
 
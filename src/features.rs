@@ -0,0 +1,295 @@
+use crate::error::*;
+use crate::model::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FieldPresence {
+    #[default]
+    Explicit,
+    Implicit,
+    LegacyRequired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EnumType {
+    Open,
+    #[default]
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RepeatedFieldEncoding {
+    Packed,
+    #[default]
+    Expanded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Utf8Validation {
+    Verify,
+    #[default]
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MessageEncoding {
+    #[default]
+    LengthPrefixed,
+    Delimited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum JsonFormat {
+    Allow,
+    #[default]
+    LegacyBestEffort,
+}
+
+/// Resolved `google.protobuf.FeatureSet`, the default value matches proto2 semantics.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FeatureSet {
+    pub field_presence: FieldPresence,
+    pub enum_type: EnumType,
+    pub repeated_field_encoding: RepeatedFieldEncoding,
+    pub utf8_validation: Utf8Validation,
+    pub message_encoding: MessageEncoding,
+    pub json_format: JsonFormat,
+}
+
+impl FeatureSet {
+    pub fn defaults(syntax: Syntax) -> FeatureSet {
+        match syntax {
+            Syntax::Proto2 => Default::default(),
+            Syntax::Proto3 => FeatureSet {
+                field_presence: FieldPresence::Implicit,
+                enum_type: EnumType::Open,
+                repeated_field_encoding: RepeatedFieldEncoding::Packed,
+                utf8_validation: Utf8Validation::Verify,
+                message_encoding: MessageEncoding::LengthPrefixed,
+                json_format: JsonFormat::Allow,
+            },
+            Syntax::Edition(_) => FeatureSet {
+                field_presence: FieldPresence::Explicit,
+                enum_type: EnumType::Open,
+                repeated_field_encoding: RepeatedFieldEncoding::Packed,
+                utf8_validation: Utf8Validation::Verify,
+                message_encoding: MessageEncoding::LengthPrefixed,
+                json_format: JsonFormat::Allow,
+            },
+        }
+    }
+
+    /// Returns a copy of `self` with the `features.*` options of a child element applied.
+    pub fn merge(&self, options: &[ProtoOption]) -> Result<FeatureSet> {
+        let mut features = *self;
+
+        for opt in options {
+            let parts = &opt.name.parts;
+            if parts.len() != 2 || parts[0].is_extension || parts[0].name != "features" {
+                continue;
+            }
+            let feature = parts[1].name.as_str();
+            let value = match &opt.value {
+                Constant::Ident(value) => value.as_str(),
+                _ => "",
+            };
+            let invalid = || Error::InvalidFeatureValue {
+                feature: feature.to_string(),
+                value: opt.raw_value.clone(),
+                location: opt.span.clone(),
+            };
+            match feature {
+                "field_presence" => {
+                    features.field_presence = match value {
+                        "EXPLICIT" => FieldPresence::Explicit,
+                        "IMPLICIT" => FieldPresence::Implicit,
+                        "LEGACY_REQUIRED" => FieldPresence::LegacyRequired,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "enum_type" => {
+                    features.enum_type = match value {
+                        "OPEN" => EnumType::Open,
+                        "CLOSED" => EnumType::Closed,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "repeated_field_encoding" => {
+                    features.repeated_field_encoding = match value {
+                        "PACKED" => RepeatedFieldEncoding::Packed,
+                        "EXPANDED" => RepeatedFieldEncoding::Expanded,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "utf8_validation" => {
+                    features.utf8_validation = match value {
+                        "VERIFY" => Utf8Validation::Verify,
+                        "NONE" => Utf8Validation::None,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "message_encoding" => {
+                    features.message_encoding = match value {
+                        "LENGTH_PREFIXED" => MessageEncoding::LengthPrefixed,
+                        "DELIMITED" => MessageEncoding::Delimited,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "json_format" => {
+                    features.json_format = match value {
+                        "ALLOW" => JsonFormat::Allow,
+                        "LEGACY_BEST_EFFORT" => JsonFormat::LegacyBestEffort,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(features)
+    }
+
+    // Like `merge`, but an option with an invalid value is reported and dropped instead of
+    // failing the whole element.
+    fn merge_valid(&self, options: &mut Vec<ProtoOption>, errors: &mut Vec<Error>) -> FeatureSet {
+        let mut features = *self;
+
+        options.retain(|opt| match features.merge(std::slice::from_ref(opt)) {
+            Ok(merged) => {
+                features = merged;
+                true
+            }
+            Err(e) => {
+                errors.push(e);
+                false
+            }
+        });
+
+        features
+    }
+
+    // proto2 and proto3 express some features through labels and options instead.
+    fn merge_legacy_field(
+        &self,
+        syntax: Syntax,
+        label: Label,
+        options: &[ProtoOption],
+    ) -> FeatureSet {
+        let mut features = *self;
+
+        if let Syntax::Edition(_) = syntax {
+            return features;
+        }
+        match label {
+            Label::Required => features.field_presence = FieldPresence::LegacyRequired,
+            Label::Optional if syntax == Syntax::Proto3 => {
+                features.field_presence = FieldPresence::Explicit
+            }
+            _ => {}
+        }
+        for opt in options.iter().filter(|o| o.name == "packed") {
+            features.repeated_field_encoding = match opt.value {
+                Constant::Bool(true) => RepeatedFieldEncoding::Packed,
+                _ => RepeatedFieldEncoding::Expanded,
+            };
+        }
+
+        features
+    }
+}
+
+fn resolve_enum(parent: &FeatureSet, e: &mut Enum, errors: &mut Vec<Error>) {
+    e.features = parent.merge_valid(&mut e.options, errors);
+    for field in e.fields.iter_mut() {
+        field.features = e.features.merge_valid(&mut field.options, errors);
+    }
+}
+
+fn resolve_field(
+    syntax: Syntax,
+    parent: &FeatureSet,
+    field: &mut NormalField,
+    errors: &mut Vec<Error>,
+) {
+    field.features = parent
+        .merge_valid(&mut field.options, errors)
+        .merge_legacy_field(syntax, field.label, &field.options);
+}
+
+fn resolve_extend(
+    syntax: Syntax,
+    parent: &FeatureSet,
+    extend: &mut Extend,
+    errors: &mut Vec<Error>,
+) {
+    for field in extend.fields.iter_mut() {
+        resolve_field(syntax, parent, field, errors);
+    }
+}
+
+fn resolve_message(
+    syntax: Syntax,
+    parent: &FeatureSet,
+    msg: &mut Message,
+    errors: &mut Vec<Error>,
+) {
+    msg.features = parent.merge_valid(&mut msg.options, errors);
+    let features = msg.features;
+
+    for field in msg.fields.iter_mut() {
+        match field {
+            MessageField::Normal(f) => {
+                resolve_field(syntax, &features, f, errors);
+            }
+            MessageField::Map(f) => {
+                f.features = features.merge_valid(&mut f.options, errors);
+            }
+            MessageField::Oneof(o) => {
+                o.features = features.merge_valid(&mut o.options, errors);
+                for f in o.fields.iter_mut() {
+                    f.features = o.features.merge_valid(&mut f.options, errors);
+                    if let Some(m) = &mut f.group {
+                        f.features.message_encoding = MessageEncoding::Delimited;
+                        resolve_message(syntax, &features, m, errors);
+                    }
+                }
+            }
+            MessageField::Group(g) => {
+                let mut group_features = features
+                    .merge_valid(&mut g.options, errors)
+                    .merge_legacy_field(syntax, g.label, &g.options);
+                group_features.message_encoding = MessageEncoding::Delimited;
+                g.features = group_features;
+                resolve_message(syntax, &features, &mut g.message, errors);
+            }
+            MessageField::Invalid => {}
+        }
+    }
+    for extend in msg.extends.iter_mut() {
+        resolve_extend(syntax, &features, extend, errors);
+    }
+    for e in msg.inner_enums.iter_mut() {
+        resolve_enum(&features, e, errors);
+    }
+    for m in msg.inner_messages.iter_mut() {
+        resolve_message(syntax, &features, m, errors);
+    }
+}
+
+/// Resolves the feature set of every element by inheriting file -> message -> field.
+///
+/// Feature options with an invalid value are removed and reported in `errors`.
+pub(crate) fn resolve(proto: &mut ProtoFile, errors: &mut Vec<Error>) {
+    let syntax = proto.syntax;
+    proto.features = FeatureSet::defaults(syntax).merge_valid(&mut proto.options, errors);
+    let features = proto.features;
+
+    for e in proto.enums.iter_mut() {
+        resolve_enum(&features, e, errors);
+    }
+    for m in proto.messages.iter_mut() {
+        resolve_message(syntax, &features, m, errors);
+    }
+    for extend in proto.extends.iter_mut() {
+        resolve_extend(syntax, &features, extend, errors);
+    }
+}
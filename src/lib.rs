@@ -2,10 +2,31 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+mod comments;
+mod cst;
+mod descriptor;
+mod diagnostic;
 mod error;
+mod features;
+mod literal;
+mod loader;
 mod model;
 mod parser;
+mod recover;
+mod resolve;
+mod validate;
+mod writer;
 
+pub use cst::*;
+pub use descriptor::*;
+pub use diagnostic::*;
 pub use error::Error;
+pub use features::*;
+pub use loader::*;
 pub use model::*;
 pub use parser::*;
+pub use prost_types;
+pub use recover::*;
+pub use resolve::*;
+pub use validate::*;
+pub use writer::*;
@@ -0,0 +1,231 @@
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use pest::iterators::Pair;
+
+use crate::error::*;
+use crate::parser::Rule;
+
+/// Decodes the digits of a decimal, octal or hex `intLit`, `None` on overflow.
+fn parse_int_lit(s: &str) -> Option<u64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if s.len() > 1 && s.starts_with('0') {
+        u64::from_str_radix(&s[1..], 8).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Splits a pair holding an `intLit`, possibly behind a sign, into its sign and magnitude.
+fn sign_and_magnitude(pair: &Pair<'_, Rule>) -> Result<(bool, Option<u64>)> {
+    let negative = pair.as_str().starts_with('-');
+    let lit = if pair.as_rule() == Rule::intLit {
+        pair.clone()
+    } else {
+        pair.clone()
+            .into_inner()
+            .find(|p| p.as_rule() == Rule::intLit)
+            .ok_or_else(|| token_not_found("intLit", pair.as_span()))?
+    };
+
+    Ok((negative, parse_int_lit(lit.as_str())))
+}
+
+/// Decodes an unsigned integer literal, checking it is at most `max`.
+pub(crate) fn parse_uint(pair: Pair<'_, Rule>, max: u64) -> Result<u64> {
+    match sign_and_magnitude(&pair)? {
+        (false, Some(v)) if v <= max => Ok(v),
+        _ => Err(integer_out_of_range(pair)),
+    }
+}
+
+/// Decodes an integer literal with an optional leading `-`, checking it lies in `min..=max`.
+pub(crate) fn parse_int(pair: Pair<'_, Rule>, min: i64, max: i64) -> Result<i64> {
+    let value = match sign_and_magnitude(&pair)? {
+        (true, Some(v)) => 0i64.checked_sub_unsigned(v),
+        (false, Some(v)) => i64::try_from(v).ok(),
+        (_, None) => None,
+    };
+
+    value
+        .filter(|v| (min..=max).contains(v))
+        .ok_or_else(|| integer_out_of_range(pair))
+}
+
+/// Takes exactly `len` hex digits, or up to `len` when `exact` is false.
+fn take_hex(chars: &mut Peekable<Chars<'_>>, len: usize, exact: bool) -> Option<u32> {
+    let mut v = 0;
+    for i in 0..len {
+        match chars.next_if(|c| c.is_ascii_hexdigit()) {
+            Some(c) => v = v * 16 + c.to_digit(16)?,
+            None if exact || i == 0 => return None,
+            None => break,
+        }
+    }
+    Some(v)
+}
+
+fn take_unicode(chars: &mut Peekable<Chars<'_>>, len: usize) -> Result<u32, String> {
+    take_hex(chars, len, true).ok_or_else(|| "\\u".to_string())
+}
+
+/// Decodes the body of a quoted string literal into the bytes it stands for, or returns the
+/// escape it cannot decode.
+pub(crate) fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    let mut buf = [0; 4];
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => out.push(0x07),
+            Some('b') => out.push(0x08),
+            Some('f') => out.push(0x0c),
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('v') => out.push(0x0b),
+            Some(c @ ('?' | '\\' | '\'' | '"')) => out.push(c as u8),
+            Some(c @ '0'..='7') => {
+                let mut v = c as u32 - '0' as u32;
+                for _ in 0..2 {
+                    match chars.next_if(|c| c.is_digit(8)) {
+                        Some(c) => v = v * 8 + (c as u32 - '0' as u32),
+                        None => break,
+                    }
+                }
+                // protoc keeps the low byte of out of range octal escapes
+                out.push(v as u8);
+            }
+            Some('x' | 'X') => {
+                let v = take_hex(&mut chars, 2, false).ok_or_else(|| "\\x".to_string())?;
+                out.push(v as u8);
+            }
+            Some(c @ ('u' | 'U')) => {
+                let mut code = take_unicode(&mut chars, if c == 'u' { 4 } else { 8 })?;
+                // a surrogate pair spelled as two escapes, as protoc accepts
+                if (0xd800..0xdc00).contains(&code) {
+                    let mut rest = chars.clone();
+                    if rest.next() == Some('\\') && rest.next() == Some('u') {
+                        if let Ok(low @ 0xdc00..=0xdfff) = take_unicode(&mut rest, 4) {
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            chars = rest;
+                        }
+                    }
+                }
+                let c = std::char::from_u32(code).ok_or_else(|| format!("\\{}{:x}", c, code))?;
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            Some(c) => return Err(format!("\\{}", c)),
+            None => return Err("\\".to_string()),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decodes a `strLit`, concatenating adjacent parts, into its bytes.
+pub(crate) fn parse_bytes(pair: Pair<'_, Rule>) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    for part in pair.into_inner() {
+        let part_span = part.as_span();
+        let body = part
+            .into_inner()
+            .find(|p| matches!(p.as_rule(), Rule::inner_str | Rule::inner_single_str))
+            .ok_or_else(|| token_not_found("inner_str", part_span))?;
+        let bytes =
+            unescape(body.as_str()).map_err(|escape| invalid_escape(escape, body.as_span()))?;
+        out.extend(bytes);
+    }
+
+    Ok(out)
+}
+
+/// Decodes a `strLit` that must hold UTF-8 text, such as an import path.
+pub(crate) fn parse_string(pair: Pair<'_, Rule>) -> Result<String> {
+    let bytes = parse_bytes(pair.clone())?;
+    String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8 {
+        literal: pair.as_str().to_string(),
+        location: pair.as_span().into(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::ProtoParser;
+    use pest::Parser;
+
+    fn pair(rule: Rule, text: &str) -> Pair<'_, Rule> {
+        ProtoParser::parse(rule, text)
+            .expect("parse literal failed")
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_int_literals() {
+        let int = |text| {
+            parse_int(
+                pair(Rule::signedIntLit, text),
+                i32::MIN as i64,
+                i32::MAX as i64,
+            )
+        };
+
+        assert_eq!(int("0").unwrap(), 0);
+        assert_eq!(int("0x10").unwrap(), 16);
+        assert_eq!(int("0XfF").unwrap(), 255);
+        assert_eq!(int("017").unwrap(), 15);
+        assert_eq!(int("-1").unwrap(), -1);
+        assert_eq!(int("- 0x80000000").unwrap(), i32::MIN as i64);
+        assert!(int("2147483648").is_err());
+        assert!(int("-2147483649").is_err());
+
+        let uint = |text| parse_uint(pair(Rule::intLit, text), u64::MAX);
+        assert_eq!(uint("18446744073709551615").unwrap(), u64::MAX);
+        assert_eq!(uint("0xffffffffffffffff").unwrap(), u64::MAX);
+        match uint("18446744073709551616") {
+            Err(Error::IntegerOutOfRange { literal, location }) => {
+                assert_eq!(literal, "18446744073709551616");
+                assert_eq!(location.start, (1, 1));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_str_literals() {
+        let bytes = |text| parse_bytes(pair(Rule::strLit, text)).unwrap();
+
+        assert_eq!(bytes(r#""plain""#), b"plain");
+        assert_eq!(bytes(r#"'single "quoted"'"#), b"single \"quoted\"");
+        assert_eq!(
+            bytes(r#""\a\b\f\n\r\t\v\?\\\'\"""#),
+            b"\x07\x08\x0c\n\r\t\x0b?\\'\""
+        );
+        assert_eq!(bytes(r#""\0\12\101\377\x7\xfF""#), b"\0\nA\xff\x07\xff");
+        assert_eq!(bytes(r#""\u00e9\U0001F600""#), "\u{e9}\u{1f600}".as_bytes());
+        assert_eq!(bytes(r#""\ud83d\ude00""#), "\u{1f600}".as_bytes());
+        assert_eq!(bytes("\"a\" 'b' /* c */ \"d\""), b"abd");
+        assert_eq!(bytes("\"h\u{e9}\"").len(), 3);
+
+        assert!(parse_bytes(pair(Rule::strLit, r#""\ud800""#)).is_err());
+        assert!(ProtoParser::parse(Rule::strLit, r#""\q""#).is_err());
+        assert!(ProtoParser::parse(Rule::strLit, r#""\u{e9}""#).is_err());
+        assert!(unescape(r#"\u{e9}"#).is_err());
+        assert!(ProtoParser::parse(Rule::strLit, "\"line\nbreak\"").is_err());
+
+        match parse_string(pair(Rule::strLit, r#""\xff""#)) {
+            Err(Error::InvalidUtf8 { literal, .. }) => assert_eq!(literal, r#""\xff""#),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::*;
+use crate::model::*;
+use crate::parser::parse_proto_text;
+
+/// Parses proto files and their imports from a list of include directories.
+#[derive(Debug, Clone, Default)]
+pub struct ProtoLoader {
+    include_paths: Vec<PathBuf>,
+}
+
+/// A set of parsed files, ordered so that every file comes after its imports.
+#[derive(Debug, Clone, Default)]
+pub struct FileSet {
+    files: Vec<ProtoFile>,
+    index: HashMap<String, usize>,
+}
+
+impl ProtoLoader {
+    pub fn new<P: Into<PathBuf>>(include_paths: impl IntoIterator<Item = P>) -> ProtoLoader {
+        ProtoLoader {
+            include_paths: include_paths.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> ProtoLoader {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    /// Loads `files` (names relative to the include paths) and everything they import.
+    pub fn load<S: AsRef<str>>(&self, files: impl IntoIterator<Item = S>) -> Result<FileSet> {
+        let mut set: FileSet = Default::default();
+        let mut stack = Vec::new();
+
+        for name in files {
+            self.visit(name.as_ref(), None, &mut stack, &mut set)?;
+        }
+
+        Ok(set)
+    }
+
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        self.include_paths
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn visit(
+        &self,
+        name: &str,
+        importer: Option<(&str, &Import)>,
+        stack: &mut Vec<String>,
+        set: &mut FileSet,
+    ) -> Result<()> {
+        if set.index.contains_key(name) {
+            return Ok(());
+        }
+        if let Some(pos) = stack.iter().position(|n| n == name) {
+            let mut cycle = stack[pos..].to_vec();
+            cycle.push(name.to_string());
+            return Err(Error::ImportCycle { cycle });
+        }
+
+        let path = match (self.find(name), importer) {
+            (Some(path), _) => path,
+            (None, Some((importer, import))) => {
+                return Err(Error::ImportNotFound {
+                    import: name.to_string(),
+                    importer: importer.to_string(),
+                    location: import.span.clone(),
+                })
+            }
+            (None, None) => {
+                return Err(Error::FileNotFound {
+                    filename: name.to_string(),
+                })
+            }
+        };
+        let in_file = |e| Error::FileError {
+            filename: name.to_string(),
+            source: Box::new(e),
+        };
+        let text =
+            std::fs::read_to_string(path).map_err(|source| in_file(Error::IoError { source }))?;
+        let mut proto = parse_proto_text(text).map_err(in_file)?;
+        proto.filename = name.to_string();
+
+        stack.push(name.to_string());
+        for import in proto.import.iter() {
+            self.visit(&import.proto_file, Some((name, import)), stack, set)?;
+        }
+        stack.pop();
+
+        set.index.insert(name.to_string(), set.files.len());
+        set.files.push(proto);
+
+        Ok(())
+    }
+}
+
+impl FileSet {
+    pub fn files(&self) -> &[ProtoFile] {
+        &self.files
+    }
+
+    pub fn files_mut(&mut self) -> &mut [ProtoFile] {
+        &mut self.files
+    }
+
+    /// Adds an already parsed file, keyed by its `filename`; it must come after its imports.
+    pub fn insert(&mut self, proto: ProtoFile) {
+        match self.index.get(&proto.filename) {
+            Some(&i) => self.files[i] = proto,
+            None => {
+                self.index.insert(proto.filename.clone(), self.files.len());
+                self.files.push(proto);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProtoFile> {
+        self.index.get(name).map(|&i| &self.files[i])
+    }
+
+    /// Direct imports of `name`.
+    pub fn dependencies(&self, name: &str) -> Vec<&ProtoFile> {
+        self.get(name)
+            .map(|proto| {
+                proto
+                    .import
+                    .iter()
+                    .filter_map(|import| self.get(&import.proto_file))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Files that `name` can see: itself, its direct imports and their public imports, transitively.
+    pub fn visible_files(&self, name: &str) -> Vec<&ProtoFile> {
+        let mut visible: Vec<&ProtoFile> = Vec::new();
+        let mut pending: Vec<&str> = vec![name];
+
+        if let Some(proto) = self.get(name) {
+            pending.extend(proto.import.iter().map(|i| i.proto_file.as_str()));
+        }
+        while let Some(name) = pending.pop() {
+            let proto = match self.get(name) {
+                Some(proto) => proto,
+                None => continue,
+            };
+            if visible.iter().any(|p| p.filename == proto.filename) {
+                continue;
+            }
+            visible.push(proto);
+            pending.extend(
+                proto
+                    .import
+                    .iter()
+                    .filter(|i| i.import_type == ImportType::Public)
+                    .map(|i| i.proto_file.as_str()),
+            );
+        }
+
+        visible
+    }
+
+    /// Edges of the import graph, from each file to the files it imports.
+    pub fn dependency_graph(&self) -> Vec<(&str, Vec<&str>)> {
+        self.files
+            .iter()
+            .map(|proto| {
+                let deps = proto.import.iter().map(|i| i.proto_file.as_str()).collect();
+                (proto.filename.as_str(), deps)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A scratch include directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_protos(dir: &str, files: &[(&str, &str)]) -> TempDir {
+        let root =
+            std::env::temp_dir().join(format!("proto3-parser-{}-{}", dir, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (name, text) in files {
+            let path = root.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).expect("create_dir_all failed");
+            std::fs::write(path, text).expect("write failed");
+        }
+        TempDir(root)
+    }
+
+    #[test]
+    fn test_load_imports() {
+        let root = write_protos(
+            "load",
+            &[
+                (
+                    "a.proto",
+                    "syntax = \"proto3\";\nimport \"dep/b.proto\";\nimport \"c.proto\";\n",
+                ),
+                (
+                    "dep/b.proto",
+                    "syntax = \"proto3\";\nimport public \"c.proto\";\n",
+                ),
+                ("c.proto", "syntax = \"proto3\";\nmessage C {}\n"),
+            ],
+        );
+
+        let set = ProtoLoader::new(vec![&root.0])
+            .load(["a.proto"])
+            .expect("load failed");
+
+        let names: Vec<_> = set.files().iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(names, vec!["c.proto", "dep/b.proto", "a.proto"]);
+        assert_eq!(set.dependencies("a.proto").len(), 2);
+        assert_eq!(
+            set.dependency_graph(),
+            vec![
+                ("c.proto", vec![]),
+                ("dep/b.proto", vec!["c.proto"]),
+                ("a.proto", vec!["dep/b.proto", "c.proto"]),
+            ]
+        );
+        let mut visible: Vec<_> = set
+            .visible_files("dep/b.proto")
+            .iter()
+            .map(|f| f.filename.as_str())
+            .collect();
+        visible.sort_unstable();
+        assert_eq!(visible, vec!["c.proto", "dep/b.proto"]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let root = write_protos(
+            "errors",
+            &[
+                ("a.proto", "syntax = \"proto3\";\nimport \"b.proto\";\n"),
+                ("b.proto", "syntax = \"proto3\";\nimport \"a.proto\";\n"),
+                (
+                    "c.proto",
+                    "syntax = \"proto3\";\n\nimport \"missing.proto\";\n",
+                ),
+            ],
+        );
+        std::fs::write(root.0.join("d.proto"), [0xff, 0xfe]).expect("write failed");
+        let loader = ProtoLoader::default().include_path(&root.0);
+
+        match loader.load(["a.proto"]) {
+            Err(Error::ImportCycle { cycle }) => {
+                assert_eq!(cycle, vec!["a.proto", "b.proto", "a.proto"])
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match loader.load(["c.proto"]) {
+            Err(Error::ImportNotFound {
+                import,
+                importer,
+                location,
+            }) => {
+                assert_eq!(import, "missing.proto");
+                assert_eq!(importer, "c.proto");
+                assert_eq!(location.start, (3, 1));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match loader.load(["nope.proto"]) {
+            Err(Error::FileNotFound { filename }) => assert_eq!(filename, "nope.proto"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match loader.load(["d.proto"]) {
+            Err(Error::FileError { filename, source }) => {
+                assert_eq!(filename, "d.proto");
+                assert!(matches!(*source, Error::IoError { .. }));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
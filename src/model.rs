@@ -1,25 +1,307 @@
-#[derive(Debug, Clone)]
-pub enum ImportType {
-    Weak,
-    Public,
+use std::fmt;
+use std::ops::Range;
+
+use crate::features::FeatureSet;
+
+/// Location of an element in the source text, lines and columns are 1-based.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub byte_range: Range<usize>,
 }
 
-impl Default for ImportType {
-    fn default() -> ImportType {
-        ImportType::Weak
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Span {
+        Span {
+            start: span.start_pos().line_col(),
+            end: span.end_pos().line_col(),
+            byte_range: span.start()..span.end(),
+        }
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comments {
+    pub leading: Option<String>,
+    pub trailing: Option<String>,
+    pub detached: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edition {
+    Edition2023,
+    Edition2024,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Syntax {
+    #[default]
+    Proto2,
+    Proto3,
+    Edition(Edition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ImportType {
+    #[default]
+    Normal,
+    Weak,
+    Public,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Import {
     pub import_type: ImportType,
     pub proto_file: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Ident(String),
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Str(String),
+    /// A string literal whose bytes are not valid UTF-8.
+    Bytes(Vec<u8>),
+    Bool(bool),
+    Aggregate(AggregateValue),
+}
+
+impl Constant {
+    /// Wraps decoded string literal bytes, as `Str` when they are valid UTF-8.
+    pub fn from_bytes(bytes: Vec<u8>) -> Constant {
+        match String::from_utf8(bytes) {
+            Ok(s) => Constant::Str(s),
+            Err(e) => Constant::Bytes(e.into_bytes()),
+        }
+    }
+}
+
+impl Default for Constant {
+    fn default() -> Constant {
+        Constant::Ident(String::new())
+    }
+}
+
+/// Renders the constant as proto source text.
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Ident(v) => f.write_str(v),
+            Constant::Bool(v) => write!(f, "{}", v),
+            Constant::Int(v) => write!(f, "{}", v),
+            Constant::Uint(v) => write!(f, "{}", v),
+            Constant::Float(v) if v.is_nan() => f.write_str("nan"),
+            Constant::Float(v) if v.is_infinite() => {
+                f.write_str(if *v > 0.0 { "inf" } else { "-inf" })
+            }
+            Constant::Float(v) => write!(f, "{:?}", v),
+            Constant::Str(v) => {
+                f.write_str("\"")?;
+                for c in v.chars() {
+                    match c {
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        // as UTF-8 bytes, `\x85` would read back as a lone byte
+                        c if c.is_control() => {
+                            for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                                write!(f, "\\{:03o}", b)?;
+                            }
+                        }
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Constant::Bytes(v) => {
+                f.write_str("\"")?;
+                for b in v.iter() {
+                    match b {
+                        b'\n' => f.write_str("\\n")?,
+                        b'\r' => f.write_str("\\r")?,
+                        b'\t' => f.write_str("\\t")?,
+                        b'"' => f.write_str("\\\"")?,
+                        b'\\' => f.write_str("\\\\")?,
+                        b' '..=b'~' => write!(f, "{}", *b as char)?,
+                        b => write!(f, "\\{:03o}", b)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Constant::Aggregate(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptionNamePart {
+    pub name: String,
+    pub is_extension: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptionName {
+    pub parts: Vec<OptionNamePart>,
+}
+
+impl fmt::Display for OptionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, part) in self.parts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            if part.is_extension {
+                write!(f, "({})", part.name)?;
+            } else {
+                f.write_str(&part.name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq<str> for OptionName {
+    fn eq(&self, other: &str) -> bool {
+        let mut rest = Some(other);
+        for (i, part) in self.parts.iter().enumerate() {
+            if i > 0 {
+                rest = rest.and_then(|r| r.strip_prefix('.'));
+            }
+            rest = if part.is_extension {
+                rest.and_then(|r| r.strip_prefix('('))
+                    .and_then(|r| r.strip_prefix(part.name.as_str()))
+                    .and_then(|r| r.strip_prefix(')'))
+            } else {
+                rest.and_then(|r| r.strip_prefix(part.name.as_str()))
+            };
+        }
+        rest == Some("")
+    }
+}
+
+impl PartialEq<&str> for OptionName {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+/// A text-format message literal, as written in aggregate option values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregateValue {
+    pub fields: Vec<AggregateField>,
+}
+
+impl AggregateValue {
+    /// Looks up a field by name, extensions named without brackets.
+    pub fn get(&self, name: &str) -> Option<&AggregateFieldValue> {
+        self.fields
+            .iter()
+            .find(|f| f.name.name == name)
+            .map(|f| &f.value)
+    }
+}
+
+impl fmt::Display for AggregateValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fields.is_empty() {
+            return f.write_str("{}");
+        }
+        f.write_str("{")?;
+        for field in self.fields.iter() {
+            write!(f, " {}", field)?;
+        }
+        f.write_str(" }")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregateField {
+    /// Extension and `Any` type URL names have `is_extension` set.
+    pub name: OptionNamePart,
+    pub value: AggregateFieldValue,
+}
+
+impl fmt::Display for AggregateField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_extension {
+            write!(f, "[{}]", self.name.name)?;
+        } else {
+            f.write_str(&self.name.name)?;
+        }
+        match &self.value {
+            AggregateFieldValue::Message(v) => write!(f, " {}", v),
+            v => write!(f, ": {}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregateFieldValue {
+    Scalar(Constant),
+    List(Vec<AggregateFieldValue>),
+    Message(AggregateValue),
+}
+
+impl Default for AggregateFieldValue {
+    fn default() -> AggregateFieldValue {
+        AggregateFieldValue::Message(Default::default())
+    }
+}
+
+impl fmt::Display for AggregateFieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateFieldValue::Scalar(v) => write!(f, "{}", v),
+            AggregateFieldValue::List(values) => {
+                f.write_str("[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str("]")
+            }
+            AggregateFieldValue::Message(v) => write!(f, "{}", v),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct ProtoOption {
-    pub name: String,
-    pub value: String,
+    pub name: OptionName,
+    pub value: Constant,
+    pub raw_value: String,
+    pub span: Span,
+}
+
+pub const MAX_FIELD_NUMBER: i32 = 536_870_911;
+pub const MAX_ENUM_VALUE: i32 = i32::MAX;
+
+/// An inclusive range of reserved numbers, `max` is stored as the upper bound of the scope.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReservedRange {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl ReservedRange {
+    pub fn contains(&self, number: i32) -> bool {
+        self.start <= number && number <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionRange {
+    pub start: i32,
+    pub end: i32,
+    pub options: Vec<ProtoOption>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -27,6 +309,9 @@ pub struct EnumField {
     pub name: String,
     pub value: i32,
     pub options: Vec<ProtoOption>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -34,9 +319,14 @@ pub struct Enum {
     pub name: String,
     pub options: Vec<ProtoOption>,
     pub fields: Vec<EnumField>,
+    pub reserved_ranges: Vec<ReservedRange>,
+    pub reserved_names: Vec<String>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum NormalFieldType {
     Double,
     Float,
@@ -53,31 +343,113 @@ pub enum NormalFieldType {
     Bool,
     Str,
     Bytes,
-    // Message(String),
-    // Enum(String),
+    Message(String),
+    Enum(String),
     MessageOrEnum(String),
+    #[default]
     Invalid,
 }
 
-impl Default for NormalFieldType {
-    fn default() -> NormalFieldType {
-        NormalFieldType::Invalid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireType {
+    Varint,
+    Fixed64,
+    LengthDelimited,
+    Fixed32,
+}
+
+impl NormalFieldType {
+    /// Wire type of a single value, unknown until `MessageOrEnum` has been linked.
+    pub fn wire_type(&self) -> Option<WireType> {
+        Some(match self {
+            NormalFieldType::Double | NormalFieldType::Fixed64 | NormalFieldType::Sfixed64 => {
+                WireType::Fixed64
+            }
+            NormalFieldType::Float | NormalFieldType::Fixed32 | NormalFieldType::Sfixed32 => {
+                WireType::Fixed32
+            }
+            NormalFieldType::Int32
+            | NormalFieldType::Int64
+            | NormalFieldType::Uint32
+            | NormalFieldType::Uint64
+            | NormalFieldType::Sint32
+            | NormalFieldType::Sint64
+            | NormalFieldType::Bool
+            | NormalFieldType::Enum(_) => WireType::Varint,
+            NormalFieldType::Str | NormalFieldType::Bytes | NormalFieldType::Message(_) => {
+                WireType::LengthDelimited
+            }
+            NormalFieldType::MessageOrEnum(_) | NormalFieldType::Invalid => return None,
+        })
+    }
+}
+
+impl fmt::Display for NormalFieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NormalFieldType::Double => "double",
+            NormalFieldType::Float => "float",
+            NormalFieldType::Int32 => "int32",
+            NormalFieldType::Int64 => "int64",
+            NormalFieldType::Uint32 => "uint32",
+            NormalFieldType::Uint64 => "uint64",
+            NormalFieldType::Sint32 => "sint32",
+            NormalFieldType::Sint64 => "sint64",
+            NormalFieldType::Fixed32 => "fixed32",
+            NormalFieldType::Fixed64 => "fixed64",
+            NormalFieldType::Sfixed32 => "sfixed32",
+            NormalFieldType::Sfixed64 => "sfixed64",
+            NormalFieldType::Bool => "bool",
+            NormalFieldType::Str => "string",
+            NormalFieldType::Bytes => "bytes",
+            NormalFieldType::Message(name)
+            | NormalFieldType::Enum(name)
+            | NormalFieldType::MessageOrEnum(name) => name,
+            NormalFieldType::Invalid => "",
+        })
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Label {
+    #[default]
+    Implicit,
+    Optional,
+    Repeated,
+    Required,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct NormalField {
-    pub repeated: bool,
+    pub label: Label,
     pub field_type: NormalFieldType,
     pub name: String,
     pub number: u32,
     pub options: Vec<ProtoOption>,
+    pub features: FeatureSet,
+    pub proto3_optional: bool,
+    pub synthetic_oneof: Option<String>,
+    pub span: Span,
+    pub comments: Comments,
+}
+
+impl NormalField {
+    pub fn default_value(&self) -> Option<&Constant> {
+        self.options
+            .iter()
+            .find(|o| o.name == "default")
+            .map(|o| &o.value)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct OneofDefine {
     pub name: String,
     pub fields: Vec<OneofField>,
+    pub options: Vec<ProtoOption>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -86,9 +458,24 @@ pub struct OneofField {
     pub field_type: NormalFieldType,
     pub number: u32,
     pub options: Vec<ProtoOption>,
+    /// The body of a proto2 `group` member, named by `field_type`; `name` is the lowercased
+    /// group name.
+    pub group: Option<Box<Message>>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
+}
+
+impl OneofField {
+    pub fn default_value(&self) -> Option<&Constant> {
+        self.options
+            .iter()
+            .find(|o| o.name == "default")
+            .map(|o| &o.value)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum MapFieldKeyType {
     Int32,
     Int64,
@@ -102,12 +489,27 @@ pub enum MapFieldKeyType {
     Sfixed64,
     Bool,
     Str,
+    #[default]
     Invalid,
 }
 
-impl Default for MapFieldKeyType {
-    fn default() -> MapFieldKeyType {
-        MapFieldKeyType::Invalid
+impl fmt::Display for MapFieldKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MapFieldKeyType::Int32 => "int32",
+            MapFieldKeyType::Int64 => "int64",
+            MapFieldKeyType::Uint32 => "uint32",
+            MapFieldKeyType::Uint64 => "uint64",
+            MapFieldKeyType::Sint32 => "sint32",
+            MapFieldKeyType::Sint64 => "sint64",
+            MapFieldKeyType::Fixed32 => "fixed32",
+            MapFieldKeyType::Fixed64 => "fixed64",
+            MapFieldKeyType::Sfixed32 => "sfixed32",
+            MapFieldKeyType::Sfixed64 => "sfixed64",
+            MapFieldKeyType::Bool => "bool",
+            MapFieldKeyType::Str => "string",
+            MapFieldKeyType::Invalid => "",
+        })
     }
 }
 
@@ -118,20 +520,44 @@ pub struct MapField {
     pub value_type: NormalFieldType,
     pub number: u32,
     pub options: Vec<ProtoOption>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+pub struct Group {
+    pub label: Label,
+    pub name: String,
+    pub number: u32,
+    pub options: Vec<ProtoOption>,
+    pub message: Box<Message>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
+}
+
+impl Group {
+    pub fn field_name(&self) -> String {
+        self.name.to_lowercase()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub enum MessageField {
     Normal(NormalField),
     Oneof(OneofDefine),
     Map(MapField),
+    Group(Group),
+    #[default]
     Invalid,
 }
 
-impl Default for MessageField {
-    fn default() -> MessageField {
-        MessageField::Invalid
-    }
+#[derive(Debug, Clone, Default)]
+pub struct Extend {
+    pub extendee: String,
+    pub fields: Vec<NormalField>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -139,8 +565,28 @@ pub struct Message {
     pub name: String,
     pub inner_messages: Vec<Message>,
     pub inner_enums: Vec<Enum>,
+    pub extends: Vec<Extend>,
     pub options: Vec<ProtoOption>,
     pub fields: Vec<MessageField>,
+    pub extension_ranges: Vec<ExtensionRange>,
+    pub reserved_ranges: Vec<ReservedRange>,
+    pub reserved_names: Vec<String>,
+    pub features: FeatureSet,
+    pub span: Span,
+    pub comments: Comments,
+}
+
+impl Message {
+    /// Names of the oneofs protoc synthesizes for proto3 `optional` fields, in field order.
+    pub fn synthetic_oneofs(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter_map(|f| match f {
+                MessageField::Normal(f) => f.synthetic_oneof.as_deref(),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -148,7 +594,11 @@ pub struct RPC {
     pub name: String,
     pub request: String,
     pub response: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
     pub options: Vec<ProtoOption>,
+    pub span: Span,
+    pub comments: Comments,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -156,15 +606,30 @@ pub struct Service {
     pub name: String,
     pub options: Vec<ProtoOption>,
     pub rpcs: Vec<RPC>,
+    pub span: Span,
+    pub comments: Comments,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct ProtoFile {
     pub filename: String,
+    pub syntax: Syntax,
     pub package: String,
+    pub package_span: Span,
     pub import: Vec<Import>,
     pub options: Vec<ProtoOption>,
     pub enums: Vec<Enum>,
     pub messages: Vec<Message>,
+    pub extends: Vec<Extend>,
     pub services: Vec<Service>,
+    pub features: FeatureSet,
+}
+
+impl ProtoFile {
+    pub fn edition(&self) -> Option<Edition> {
+        match self.syntax {
+            Syntax::Edition(edition) => Some(edition),
+            _ => None,
+        }
+    }
 }
@@ -1,25 +1,189 @@
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::{Mutex, PoisonError};
 
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 
 use crate::error::*;
+use crate::literal::*;
 use crate::model::*;
 
 #[derive(Parser)]
 #[grammar = "proto.pest"]
 pub struct ProtoParser;
 
+// pest only records the tokens a failed parse expected with error detail on, which is a
+// process-wide switch; it stays on while any thread is re-parsing in `parse_detailed`.
+static DETAILED_PARSES: Mutex<usize> = Mutex::new(0);
+
+fn set_detailed(entering: bool) {
+    let mut parses = DETAILED_PARSES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if entering {
+        *parses += 1;
+    } else {
+        *parses -= 1;
+    }
+    pest::set_error_detail(*parses > 0);
+}
+
+// Runs the grammar with error detail, for failures that need a diagnostic.
+fn parse_detailed(
+    rule: Rule,
+    text: &str,
+) -> std::result::Result<Pairs<'_, Rule>, pest::error::Error<Rule>> {
+    set_detailed(true);
+    let result = ProtoParser::parse(rule, text);
+    set_detailed(false);
+    result
+}
+
+/// Runs the grammar from `rule`, turning a failure into a located `GrammarError`.
+pub(crate) fn parse_rule(rule: Rule, text: &str) -> Result<Pairs<'_, Rule>> {
+    ProtoParser::parse(rule, text)
+        .or_else(|_| parse_detailed(rule, text))
+        .map_err(|e| grammar_error(e, text, 0))
+}
+
+/// The first child of `pair`, which the grammar guarantees is a `token`.
+fn first_child<'i>(pair: Pair<'i, Rule>, token: &str) -> Result<Pair<'i, Rule>> {
+    let span = pair.as_span();
+    pair.into_inner()
+        .next()
+        .ok_or_else(|| token_not_found(token, span))
+}
+
+fn parse_field_number(pair: Pair<'_, Rule>) -> Result<u32> {
+    Ok(parse_uint(pair, i32::MAX as u64)? as u32)
+}
+
+fn parse_constant(pair: Pair<'_, Rule>) -> Result<Constant> {
+    let negative = pair.as_str().starts_with('-');
+    let value = first_child(pair.clone(), "constant")?;
+
+    Ok(match value.as_rule() {
+        Rule::fullIdent => match value.as_str() {
+            "true" => Constant::Bool(true),
+            "false" => Constant::Bool(false),
+            s => Constant::Ident(s.to_string()),
+        },
+        Rule::intLit if negative => Constant::Int(parse_int(pair, i64::MIN, 0)?),
+        Rule::intLit => Constant::Uint(parse_uint(value, u64::MAX)?),
+        Rule::floatLit => {
+            let v: f64 = value
+                .as_str()
+                .parse()
+                .map_err(|source| Error::ParseFloatError {
+                    source,
+                    location: value.as_span().into(),
+                })?;
+            Constant::Float(if negative { -v } else { v })
+        }
+        Rule::strLit => Constant::from_bytes(parse_bytes(value)?),
+        Rule::boolLit => Constant::Bool(value.as_str() == "true"),
+        Rule::aggregate => Constant::Aggregate(parse_aggregate(value)?),
+        _ => return Err(unexpect_token(value)),
+    })
+}
+
+fn parse_aggregate(pair: Pair<'_, Rule>) -> Result<AggregateValue> {
+    let fields = pair
+        .into_inner()
+        .map(parse_aggregate_field)
+        .collect::<Result<_>>()?;
+
+    Ok(AggregateValue { fields })
+}
+
+fn parse_aggregate_value(pair: Pair<'_, Rule>) -> Result<AggregateFieldValue> {
+    Ok(match pair.as_rule() {
+        Rule::aggregateValue => {
+            let value = first_child(pair, "aggregateValue")?;
+            parse_aggregate_value(value)?
+        }
+        Rule::aggregate => AggregateFieldValue::Message(parse_aggregate(pair)?),
+        Rule::aggregateList => AggregateFieldValue::List(
+            pair.into_inner()
+                .map(parse_aggregate_value)
+                .collect::<Result<_>>()?,
+        ),
+        Rule::constant => AggregateFieldValue::Scalar(parse_constant(pair)?),
+        _ => return Err(unexpect_token(pair)),
+    })
+}
+
+fn parse_aggregate_field(pair: Pair<'_, Rule>) -> Result<AggregateField> {
+    let mut field: AggregateField = Default::default();
+
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::aggregateFieldName => {
+                let name = first_child(part, "aggregateFieldName")?;
+                field.name = OptionNamePart {
+                    name: name.as_str().to_string(),
+                    is_extension: name.as_rule() == Rule::aggregateExtensionName,
+                };
+            }
+            _ => field.value = parse_aggregate_value(part)?,
+        }
+    }
+
+    Ok(field)
+}
+
+/// Parses the text-format body of an aggregate value, without the enclosing braces.
+pub(crate) fn parse_aggregate_text(text: &str) -> Result<AggregateValue> {
+    let text = format!("{{{}}}", text);
+    let start = pest::Position::from_start(&text);
+    let pair = parse_rule(Rule::aggregate, &text)?
+        .next()
+        .ok_or_else(|| token_not_found("aggregate", start.span(&start)))?;
+    if pair.as_str().len() != text.len() {
+        return Err(token_not_found("}", pair.as_span()));
+    }
+
+    parse_aggregate(pair)
+}
+
+fn parse_option_name(pair: Pair<'_, Rule>) -> Result<OptionName> {
+    let mut name: OptionName = Default::default();
+
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::ident => name.parts.push(OptionNamePart {
+                name: part.as_str().to_string(),
+                is_extension: false,
+            }),
+            Rule::extensionName => {
+                let path = first_child(part, "extensionPath")?;
+                name.parts.push(OptionNamePart {
+                    name: path.as_str().to_string(),
+                    is_extension: true,
+                });
+            }
+            _ => return Err(unexpect_token(part)),
+        }
+    }
+
+    Ok(name)
+}
+
 fn parse_option(pair: Pair<'_, Rule>) -> Result<ProtoOption> {
-    let mut new_opt: ProtoOption = Default::default();
+    let mut new_opt = ProtoOption {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for opt in pair.into_inner() {
         match opt.as_rule() {
             Rule::optionName => {
-                new_opt.name = opt.as_str().to_string();
+                new_opt.name = parse_option_name(opt)?;
             }
             Rule::constant => {
-                new_opt.value = opt.as_str().to_string();
+                new_opt.raw_value = opt.as_str().to_string();
+                new_opt.value = parse_constant(opt)?;
             }
             _ => return Err(unexpect_token(opt)),
         }
@@ -28,8 +192,27 @@ fn parse_option(pair: Pair<'_, Rule>) -> Result<ProtoOption> {
     Ok(new_opt)
 }
 
+fn parse_field_options(pair: Pair<'_, Rule>) -> Result<Vec<ProtoOption>> {
+    let mut opts = Vec::new();
+
+    for opt in pair.into_inner() {
+        match opt.as_rule() {
+            Rule::fieldOption => {
+                let new_opt = parse_option(opt)?;
+                opts.push(new_opt);
+            }
+            _ => return Err(unexpect_token(opt)),
+        }
+    }
+
+    Ok(opts)
+}
+
 fn parse_import(pair: Pair<'_, Rule>) -> Result<Import> {
-    let mut new_import: Import = Default::default();
+    let mut new_import = Import {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for part in pair.into_inner() {
         match part.as_rule() {
@@ -40,11 +223,7 @@ fn parse_import(pair: Pair<'_, Rule>) -> Result<Import> {
                 new_import.import_type = ImportType::Public;
             }
             Rule::strLit => {
-                let s = part
-                    .into_inner()
-                    .nth(1)
-                    .ok_or_else(|| token_not_found("inner_str"))?;
-                new_import.proto_file = s.as_str().to_string();
+                new_import.proto_file = parse_string(part)?;
             }
             _ => return Err(unexpect_token(part)),
         }
@@ -53,8 +232,134 @@ fn parse_import(pair: Pair<'_, Rule>) -> Result<Import> {
     Ok(new_import)
 }
 
-fn parse_enum(pair: Pair<'_, Rule>) -> Result<Enum> {
-    let mut new_enum: Enum = Default::default();
+/// Parses ranges whose bounds are at least `min`, with `max` standing for the `max` keyword.
+fn parse_ranges(pair: Pair<'_, Rule>, min: i32, max: i32) -> Result<Vec<ReservedRange>> {
+    let mut ranges = Vec::new();
+
+    for range in pair.into_inner() {
+        let span = range.as_span();
+        let mut bounds = range.into_inner();
+        let start = bounds
+            .next()
+            .ok_or_else(|| token_not_found("signedIntLit", span))?;
+        let start = parse_int(start, min as i64, i32::MAX as i64)? as i32;
+        let end = match bounds.next() {
+            Some(end) => match end.as_rule() {
+                Rule::max => max,
+                Rule::signedIntLit => parse_int(end, min as i64, i32::MAX as i64)? as i32,
+                _ => return Err(unexpect_token(end)),
+            },
+            None => start,
+        };
+        ranges.push(ReservedRange { start, end });
+    }
+
+    Ok(ranges)
+}
+
+fn parse_reserved(
+    pair: Pair<'_, Rule>,
+    min: i32,
+    max: i32,
+) -> Result<(Vec<ReservedRange>, Vec<String>)> {
+    let mut ranges = Vec::new();
+    let mut names = Vec::new();
+
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::ranges => {
+                ranges.extend(parse_ranges(part, min, max)?);
+            }
+            Rule::fieldNames => {
+                for name in part.into_inner() {
+                    let name = first_child(name, "reservedName")?;
+                    match name.as_rule() {
+                        Rule::strLit => names.push(parse_string(name)?),
+                        Rule::fieldName => names.push(name.as_str().to_string()),
+                        _ => return Err(unexpect_token(name)),
+                    }
+                }
+            }
+            _ => return Err(unexpect_token(part)),
+        }
+    }
+
+    Ok((ranges, names))
+}
+
+fn parse_extensions(pair: Pair<'_, Rule>) -> Result<Vec<ExtensionRange>> {
+    let mut ranges = Vec::new();
+    let mut options = Vec::new();
+
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::ranges => {
+                ranges = parse_ranges(part, 0, MAX_FIELD_NUMBER)?;
+            }
+            Rule::fieldOptions => {
+                options = parse_field_options(part)?;
+            }
+            _ => return Err(unexpect_token(part)),
+        }
+    }
+
+    Ok(ranges
+        .into_iter()
+        .map(|r| ExtensionRange {
+            start: r.start,
+            end: r.end,
+            options: options.clone(),
+        })
+        .collect())
+}
+
+fn parse_enum_item(part: Pair<'_, Rule>, new_enum: &mut Enum) -> Result<()> {
+    match part.as_rule() {
+        Rule::option => {
+            let new_opt = parse_option(part)?;
+            new_enum.options.push(new_opt);
+        }
+        Rule::enumField => {
+            let mut new_field = EnumField {
+                span: part.as_span().into(),
+                ..Default::default()
+            };
+            for field in part.into_inner() {
+                match field.as_rule() {
+                    Rule::ident => {
+                        new_field.name = field.as_str().to_string();
+                    }
+                    Rule::signedIntLit => {
+                        new_field.value =
+                            parse_int(field, i32::MIN as i64, i32::MAX as i64)? as i32;
+                    }
+                    Rule::enumValueOption => {
+                        let new_opt = parse_option(field)?;
+                        new_field.options.push(new_opt);
+                    }
+                    _ => return Err(unexpect_token(field)),
+                }
+            }
+            new_enum.fields.push(new_field);
+        }
+        Rule::reserved => {
+            let (ranges, names) = parse_reserved(part, i32::MIN, MAX_ENUM_VALUE)?;
+            new_enum.reserved_ranges.extend(ranges);
+            new_enum.reserved_names.extend(names);
+        }
+        Rule::emptyStatement => {}
+        Rule::skipped | Rule::unclosed => return Err(skipped_statement(part, Rule::enumStatement)),
+        _ => return Err(unexpect_token(part)),
+    }
+
+    Ok(())
+}
+
+fn parse_enum(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<Enum> {
+    let mut new_enum = Enum {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
@@ -63,32 +368,8 @@ fn parse_enum(pair: Pair<'_, Rule>) -> Result<Enum> {
             }
             Rule::enumBody => {
                 for part in entry.into_inner() {
-                    match part.as_rule() {
-                        Rule::option => {
-                            let new_opt = parse_option(part)?;
-                            new_enum.options.push(new_opt);
-                        }
-                        Rule::enumField => {
-                            let mut new_field: EnumField = Default::default();
-                            for field in part.into_inner() {
-                                match field.as_rule() {
-                                    Rule::ident => {
-                                        new_field.name = field.as_str().to_string();
-                                    }
-                                    Rule::intLit => {
-                                        new_field.value = field.as_str().parse()?;
-                                    }
-                                    Rule::enumValueOption => {
-                                        let new_opt = parse_option(field)?;
-                                        new_field.options.push(new_opt);
-                                    }
-                                    _ => return Err(unexpect_token(field)),
-                                }
-                            }
-                            new_enum.fields.push(new_field);
-                        }
-                        Rule::emptyStatement => {}
-                        _ => return Err(unexpect_token(part)),
+                    if let Err(e) = parse_enum_item(part, &mut new_enum) {
+                        errors.push(e);
                     }
                 }
             }
@@ -120,40 +401,43 @@ fn parse_message_normal_field_type(pair: Pair<'_, Rule>) -> Result<NormalFieldTy
             let s = pair.as_str();
             NormalFieldType::MessageOrEnum(s.to_string())
         }
-        // Rule::messageType => {
-        //     let s = pair.as_str();
-        //     NormalFieldType::Message(s.to_string())
-        // }
-        // Rule::enumType => {
-        //     let s = pair.as_str();
-        //     NormalFieldType::Enum(s.to_string())
-        // }
         _ => return Err(unexpect_token(pair)),
     })
 }
 
+fn parse_label(pair: Pair<'_, Rule>) -> Result<Label> {
+    let label = first_child(pair, "label")?;
+
+    Ok(match label.as_rule() {
+        Rule::optional => Label::Optional,
+        Rule::repeated => Label::Repeated,
+        Rule::required => Label::Required,
+        _ => return Err(unexpect_token(label)),
+    })
+}
+
 fn parse_message_normal_field(pair: Pair<'_, Rule>) -> Result<NormalField> {
-    let mut new_field: NormalField = Default::default();
+    let mut new_field = NormalField {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
-            Rule::repeated => {
-                new_field.repeated = true;
+            Rule::label => {
+                new_field.label = parse_label(entry)?;
             }
             Rule::normalType => {
-                let t = entry
-                    .into_inner()
-                    .next()
-                    .ok_or_else(|| token_not_found("normalType"))?;
+                let t = first_child(entry, "normalType")?;
                 new_field.field_type = parse_message_normal_field_type(t)?;
             }
             Rule::fieldName => {
                 new_field.name = entry.as_str().to_string();
             }
-            Rule::fieldNumber => new_field.number = entry.as_str().parse()?,
+            Rule::fieldNumber => new_field.number = parse_field_number(entry)?,
             Rule::fieldOptions => {
-                let opt = parse_option(entry)?;
-                new_field.options.push(opt);
+                let opts = parse_field_options(entry)?;
+                new_field.options.extend(opts);
             }
             _ => return Err(unexpect_token(entry)),
         }
@@ -163,26 +447,26 @@ fn parse_message_normal_field(pair: Pair<'_, Rule>) -> Result<NormalField> {
 }
 
 fn parse_message_oneof_field(pair: Pair<'_, Rule>) -> Result<OneofField> {
-    let mut new_field: OneofField = Default::default();
+    let mut new_field = OneofField {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
             Rule::normalType => {
-                let t = entry
-                    .into_inner()
-                    .next()
-                    .ok_or_else(|| token_not_found("normalType"))?;
+                let t = first_child(entry, "normalType")?;
                 new_field.field_type = parse_message_normal_field_type(t)?;
             }
             Rule::fieldName => {
                 new_field.name = entry.as_str().to_string();
             }
             Rule::fieldNumber => {
-                new_field.number = entry.as_str().parse()?;
+                new_field.number = parse_field_number(entry)?;
             }
             Rule::fieldOptions => {
-                let opt = parse_option(entry)?;
-                new_field.options.push(opt);
+                let opts = parse_field_options(entry)?;
+                new_field.options.extend(opts);
             }
             _ => return Err(unexpect_token(entry)),
         }
@@ -191,19 +475,36 @@ fn parse_message_oneof_field(pair: Pair<'_, Rule>) -> Result<OneofField> {
     Ok(new_field)
 }
 
-fn parse_message_oneof_define(pair: Pair<'_, Rule>) -> Result<OneofDefine> {
-    let mut new_oneof: OneofDefine = Default::default();
+fn parse_message_oneof_define(
+    pair: Pair<'_, Rule>,
+    errors: &mut Vec<Error>,
+) -> Result<OneofDefine> {
+    let mut new_oneof = OneofDefine {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
             Rule::oneofName => {
                 new_oneof.name = entry.as_str().to_string();
             }
-            Rule::oneofField => {
-                let field = parse_message_oneof_field(entry)?;
-                new_oneof.fields.push(field);
-            }
+            Rule::option => match parse_option(entry) {
+                Ok(new_opt) => new_oneof.options.push(new_opt),
+                Err(e) => errors.push(e),
+            },
+            Rule::oneofField => match parse_message_oneof_field(entry) {
+                Ok(field) => new_oneof.fields.push(field),
+                Err(e) => errors.push(e),
+            },
+            Rule::group => match parse_message_group(entry, errors).and_then(oneof_group) {
+                Ok(field) => new_oneof.fields.push(field),
+                Err(e) => errors.push(e),
+            },
             Rule::emptyStatement => {}
+            Rule::skipped | Rule::unclosed => {
+                errors.push(skipped_statement(entry, Rule::oneofStatement))
+            }
             _ => return Err(unexpect_token(entry)),
         }
     }
@@ -211,6 +512,27 @@ fn parse_message_oneof_define(pair: Pair<'_, Rule>) -> Result<OneofDefine> {
     Ok(new_oneof)
 }
 
+// A group in a oneof is a member typed by its own body, and takes no label.
+fn oneof_group(group: Group) -> Result<OneofField> {
+    if group.label != Label::Implicit {
+        return Err(Error::InvalidLabel {
+            message: "Fields in oneofs must not have labels (required / optional / repeated)."
+                .to_string(),
+            location: group.span,
+        });
+    }
+
+    Ok(OneofField {
+        name: group.field_name(),
+        field_type: NormalFieldType::MessageOrEnum(group.name),
+        number: group.number,
+        options: group.options,
+        group: Some(group.message),
+        span: group.span,
+        ..Default::default()
+    })
+}
+
 fn parse_message_map_field_key_type(pair: Pair<'_, Rule>) -> Result<MapFieldKeyType> {
     Ok(match pair.as_rule() {
         Rule::int32Type => MapFieldKeyType::Int32,
@@ -230,33 +552,30 @@ fn parse_message_map_field_key_type(pair: Pair<'_, Rule>) -> Result<MapFieldKeyT
 }
 
 fn parse_message_map_field(pair: Pair<'_, Rule>) -> Result<MapField> {
-    let mut new_field: MapField = Default::default();
+    let mut new_field = MapField {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
             Rule::keyType => {
-                let t = entry
-                    .into_inner()
-                    .next()
-                    .ok_or_else(|| token_not_found("keyType"))?;
+                let t = first_child(entry, "keyType")?;
                 new_field.key_type = parse_message_map_field_key_type(t)?;
             }
             Rule::normalType => {
-                let t = entry
-                    .into_inner()
-                    .next()
-                    .ok_or_else(|| token_not_found("normalType"))?;
+                let t = first_child(entry, "normalType")?;
                 new_field.value_type = parse_message_normal_field_type(t)?;
             }
             Rule::mapName => {
                 new_field.name = entry.as_str().to_string();
             }
             Rule::fieldNumber => {
-                new_field.number = entry.as_str().parse()?;
+                new_field.number = parse_field_number(entry)?;
             }
             Rule::fieldOptions => {
-                let opt = parse_option(entry)?;
-                new_field.options.push(opt);
+                let opts = parse_field_options(entry)?;
+                new_field.options.extend(opts);
             }
             _ => return Err(unexpect_token(entry)),
         }
@@ -265,8 +584,135 @@ fn parse_message_map_field(pair: Pair<'_, Rule>) -> Result<MapField> {
     Ok(new_field)
 }
 
-fn parse_message(pair: Pair<'_, Rule>) -> Result<Message> {
-    let mut new_message: Message = Default::default();
+fn parse_extend(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<Extend> {
+    let mut new_extend = Extend {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
+
+    for entry in pair.into_inner() {
+        match entry.as_rule() {
+            Rule::messageType => {
+                new_extend.extendee = entry.as_str().to_string();
+            }
+            Rule::field => match parse_message_normal_field(entry) {
+                Ok(field) => new_extend.fields.push(field),
+                Err(e) => errors.push(e),
+            },
+            Rule::emptyStatement => {}
+            Rule::skipped | Rule::unclosed => {
+                errors.push(skipped_statement(entry, Rule::extendStatement))
+            }
+            _ => return Err(unexpect_token(entry)),
+        }
+    }
+
+    Ok(new_extend)
+}
+
+fn parse_message_group(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<Group> {
+    let mut new_group = Group {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
+
+    for entry in pair.into_inner() {
+        match entry.as_rule() {
+            Rule::label => {
+                new_group.label = parse_label(entry)?;
+            }
+            Rule::groupName => {
+                new_group.name = entry.as_str().to_string();
+                new_group.message.name = new_group.name.clone();
+                new_group.message.span = new_group.span.clone();
+            }
+            Rule::fieldNumber => {
+                new_group.number = parse_field_number(entry)?;
+            }
+            Rule::fieldOptions => {
+                let opts = parse_field_options(entry)?;
+                new_group.options.extend(opts);
+            }
+            Rule::messageBody => {
+                parse_message_body(entry, &mut new_group.message, errors);
+            }
+            _ => return Err(unexpect_token(entry)),
+        }
+    }
+
+    Ok(new_group)
+}
+
+fn parse_message_item(
+    part: Pair<'_, Rule>,
+    new_message: &mut Message,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    match part.as_rule() {
+        Rule::field => {
+            let field = parse_message_normal_field(part)?;
+            new_message.fields.push(MessageField::Normal(field));
+        }
+        Rule::group => {
+            let group = parse_message_group(part, errors)?;
+            new_message.fields.push(MessageField::Group(group));
+        }
+        Rule::mapField => {
+            let field = parse_message_map_field(part)?;
+            new_message.fields.push(MessageField::Map(field));
+        }
+        Rule::oneof => {
+            let oneof = parse_message_oneof_define(part, errors)?;
+            new_message.fields.push(MessageField::Oneof(oneof));
+        }
+        Rule::Enum => {
+            let enum_def = parse_enum(part, errors)?;
+            new_message.inner_enums.push(enum_def);
+        }
+        Rule::Message => {
+            let msg_def = parse_message(part, errors)?;
+            new_message.inner_messages.push(msg_def);
+        }
+        Rule::Extend => {
+            let extend = parse_extend(part, errors)?;
+            new_message.extends.push(extend);
+        }
+        Rule::option => {
+            let new_opt = parse_option(part)?;
+            new_message.options.push(new_opt);
+        }
+        Rule::extensions => {
+            let ranges = parse_extensions(part)?;
+            new_message.extension_ranges.extend(ranges);
+        }
+        Rule::reserved => {
+            let (ranges, names) = parse_reserved(part, 0, MAX_FIELD_NUMBER)?;
+            new_message.reserved_ranges.extend(ranges);
+            new_message.reserved_names.extend(names);
+        }
+        Rule::emptyStatement => {}
+        Rule::skipped | Rule::unclosed => {
+            return Err(skipped_statement(part, Rule::messageStatement))
+        }
+        _ => return Err(unexpect_token(part)),
+    }
+
+    Ok(())
+}
+
+fn parse_message_body(pair: Pair<'_, Rule>, new_message: &mut Message, errors: &mut Vec<Error>) {
+    for part in pair.into_inner() {
+        if let Err(e) = parse_message_item(part, new_message, errors) {
+            errors.push(e);
+        }
+    }
+}
+
+fn parse_message(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<Message> {
+    let mut new_message = Message {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
@@ -274,36 +720,7 @@ fn parse_message(pair: Pair<'_, Rule>) -> Result<Message> {
                 new_message.name = entry.as_str().to_string();
             }
             Rule::messageBody => {
-                for part in entry.into_inner() {
-                    match part.as_rule() {
-                        Rule::field => {
-                            let field = parse_message_normal_field(part)?;
-                            new_message.fields.push(MessageField::Normal(field));
-                        }
-                        Rule::mapField => {
-                            let field = parse_message_map_field(part)?;
-                            new_message.fields.push(MessageField::Map(field));
-                        }
-                        Rule::oneof => {
-                            let oneof = parse_message_oneof_define(part)?;
-                            new_message.fields.push(MessageField::Oneof(oneof));
-                        }
-                        Rule::Enum => {
-                            let enum_def = parse_enum(part)?;
-                            new_message.inner_enums.push(enum_def);
-                        }
-                        Rule::Message => {
-                            let msg_def = parse_message(part)?;
-                            new_message.inner_messages.push(msg_def);
-                        }
-                        Rule::option => {
-                            let new_opt = parse_option(part)?;
-                            new_message.options.push(new_opt);
-                        }
-                        Rule::emptyStatement => {}
-                        _ => return Err(unexpect_token(part)),
-                    }
-                }
+                parse_message_body(entry, &mut new_message, errors);
             }
             Rule::emptyStatement => {}
             _ => {}
@@ -313,23 +730,157 @@ fn parse_message(pair: Pair<'_, Rule>) -> Result<Message> {
     Ok(new_message)
 }
 
-fn parse_rpc(pair: Pair<'_, Rule>) -> Result<RPC> {
-    let mut new_rpc: RPC = Default::default();
+fn assign_synthetic_oneofs(message: &mut Message) {
+    // protoc avoids every field name in the message, oneof members included, and every oneof name
+    let mut names: HashSet<String> = HashSet::new();
+    for field in message.fields.iter() {
+        match field {
+            MessageField::Normal(f) => {
+                names.insert(f.name.clone());
+            }
+            MessageField::Map(f) => {
+                names.insert(f.name.clone());
+            }
+            MessageField::Group(g) => {
+                names.insert(g.field_name());
+            }
+            MessageField::Oneof(o) => {
+                names.insert(o.name.clone());
+                names.extend(o.fields.iter().map(|f| f.name.clone()));
+            }
+            MessageField::Invalid => {}
+        }
+    }
+
+    for field in message.fields.iter_mut() {
+        if let MessageField::Normal(f) = field {
+            if f.label != Label::Optional {
+                continue;
+            }
+            let mut oneof_name = f.name.clone();
+            if !oneof_name.starts_with('_') {
+                oneof_name.insert(0, '_');
+            }
+            while names.contains(&oneof_name) {
+                oneof_name.insert(0, 'X');
+            }
+            names.insert(oneof_name.clone());
+            f.proto3_optional = true;
+            f.synthetic_oneof = Some(oneof_name);
+        }
+    }
+    for field in message.fields.iter_mut() {
+        match field {
+            MessageField::Group(g) => assign_synthetic_oneofs(&mut g.message),
+            MessageField::Oneof(o) => {
+                for m in o.fields.iter_mut().filter_map(|f| f.group.as_mut()) {
+                    assign_synthetic_oneofs(m);
+                }
+            }
+            _ => {}
+        }
+    }
+    for m in message.inner_messages.iter_mut() {
+        assign_synthetic_oneofs(m);
+    }
+}
+
+// Why `label` is not allowed on a field outside a oneof, if it is not.
+fn label_error(syntax: Syntax, label: Label, location: &Span) -> Option<Error> {
+    let message = match (syntax, label) {
+        (Syntax::Proto2, Label::Implicit) => {
+            "Expected \"required\", \"optional\", or \"repeated\"."
+        }
+        (Syntax::Edition(_), Label::Optional) => {
+            "Label \"optional\" is not supported in editions. By default, all singular fields have presence unless features.field_presence is set."
+        }
+        (Syntax::Edition(_), Label::Required) => {
+            "Label \"required\" is not supported in editions, use features.field_presence = LEGACY_REQUIRED."
+        }
+        _ => return None,
+    };
+
+    Some(Error::InvalidLabel {
+        message: message.to_string(),
+        location: location.clone(),
+    })
+}
+
+fn check_field_labels(syntax: Syntax, fields: &mut Vec<NormalField>, errors: &mut Vec<Error>) {
+    fields.retain(|f| match label_error(syntax, f.label, &f.span) {
+        Some(e) => {
+            errors.push(e);
+            false
+        }
+        None => true,
+    });
+}
+
+/// Drops the fields of `message` whose label the syntax does not allow, reporting them.
+fn check_labels(syntax: Syntax, message: &mut Message, errors: &mut Vec<Error>) {
+    message.fields.retain(|field| {
+        let error = match field {
+            MessageField::Normal(f) => label_error(syntax, f.label, &f.span),
+            MessageField::Group(g) => label_error(syntax, g.label, &g.span),
+            _ => None,
+        };
+        match error {
+            Some(e) => {
+                errors.push(e);
+                false
+            }
+            None => true,
+        }
+    });
+    for field in message.fields.iter_mut() {
+        match field {
+            MessageField::Group(g) => check_labels(syntax, &mut g.message, errors),
+            MessageField::Oneof(o) => {
+                for m in o.fields.iter_mut().filter_map(|f| f.group.as_mut()) {
+                    check_labels(syntax, m, errors);
+                }
+            }
+            _ => {}
+        }
+    }
+    for extend in message.extends.iter_mut() {
+        check_field_labels(syntax, &mut extend.fields, errors);
+    }
+    for m in message.inner_messages.iter_mut() {
+        check_labels(syntax, m, errors);
+    }
+}
+
+fn parse_rpc(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<RPC> {
+    let mut new_rpc = RPC {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
-    let mut entry = pair.into_inner();
-    let name = entry.next().ok_or_else(|| token_not_found("rpcName"))?;
+    let span = pair.as_span();
+    let mut entry = pair.into_inner().peekable();
+    let name = entry
+        .next()
+        .ok_or_else(|| token_not_found("rpcName", span))?;
     new_rpc.name = name.as_str().to_string();
-    let req = entry.next().ok_or_else(|| token_not_found("messageType"))?;
+    new_rpc.client_streaming = entry.next_if(|e| e.as_rule() == Rule::stream).is_some();
+    let req = entry
+        .next()
+        .ok_or_else(|| token_not_found("messageType", span))?;
     new_rpc.request = req.as_str().to_string();
-    let resp = entry.next().ok_or_else(|| token_not_found("messageType"))?;
+    new_rpc.server_streaming = entry.next_if(|e| e.as_rule() == Rule::stream).is_some();
+    let resp = entry
+        .next()
+        .ok_or_else(|| token_not_found("messageType", span))?;
     new_rpc.response = resp.as_str().to_string();
     for e in entry {
         match e.as_rule() {
-            Rule::option => {
-                let new_opt = parse_option(e)?;
-                new_rpc.options.push(new_opt);
-            }
+            Rule::option => match parse_option(e) {
+                Ok(new_opt) => new_rpc.options.push(new_opt),
+                Err(err) => errors.push(err),
+            },
             Rule::emptyStatement => {}
+            Rule::skipped | Rule::unclosed => errors.push(skipped_statement(e, Rule::rpcStatement)),
             _ => return Err(unexpect_token(e)),
         }
     }
@@ -337,23 +888,29 @@ fn parse_rpc(pair: Pair<'_, Rule>) -> Result<RPC> {
     Ok(new_rpc)
 }
 
-fn parse_service(pair: Pair<'_, Rule>) -> Result<Service> {
-    let mut new_service: Service = Default::default();
+fn parse_service(pair: Pair<'_, Rule>, errors: &mut Vec<Error>) -> Result<Service> {
+    let mut new_service = Service {
+        span: pair.as_span().into(),
+        ..Default::default()
+    };
 
     for entry in pair.into_inner() {
         match entry.as_rule() {
             Rule::serviceName => {
                 new_service.name = entry.as_str().to_string();
             }
-            Rule::option => {
-                let new_opt = parse_option(entry)?;
-                new_service.options.push(new_opt);
-            }
-            Rule::rpc => {
-                let new_rpc = parse_rpc(entry)?;
-                new_service.rpcs.push(new_rpc);
-            }
+            Rule::option => match parse_option(entry) {
+                Ok(new_opt) => new_service.options.push(new_opt),
+                Err(e) => errors.push(e),
+            },
+            Rule::rpc => match parse_rpc(entry, errors) {
+                Ok(new_rpc) => new_service.rpcs.push(new_rpc),
+                Err(e) => errors.push(e),
+            },
             Rule::emptyStatement => {}
+            Rule::skipped | Rule::unclosed => {
+                errors.push(skipped_statement(entry, Rule::serviceStatement))
+            }
             _ => return Err(unexpect_token(entry)),
         }
     }
@@ -361,65 +918,197 @@ fn parse_service(pair: Pair<'_, Rule>) -> Result<Service> {
     Ok(new_service)
 }
 
+fn parse_syntax(pair: Pair<'_, Rule>) -> Result<Syntax> {
+    let s = first_child(pair, "strLit")?;
+    let location = s.as_span().into();
+
+    Ok(match parse_string(s)?.as_str() {
+        "proto2" => Syntax::Proto2,
+        "proto3" => Syntax::Proto3,
+        other => {
+            return Err(Error::UnsupportedSyntax {
+                syntax: other.to_string(),
+                location,
+            })
+        }
+    })
+}
+
+fn parse_edition(pair: Pair<'_, Rule>) -> Result<Syntax> {
+    let s = first_child(pair, "strLit")?;
+    let location = s.as_span().into();
+
+    Ok(match parse_string(s)?.as_str() {
+        "2023" => Syntax::Edition(Edition::Edition2023),
+        "2024" => Syntax::Edition(Edition::Edition2024),
+        other => {
+            return Err(Error::UnsupportedEdition {
+                edition: other.to_string(),
+                location,
+            })
+        }
+    })
+}
+
+/// Reads and parses `filepath`, errors are wrapped in `Error::FileError` naming the file.
 pub fn parse_proto_file(filepath: impl AsRef<Path>) -> Result<ProtoFile> {
-    let text = std::fs::read_to_string(filepath)?;
+    let filepath = filepath.as_ref();
+    let filename = filepath.to_string_lossy().into_owned();
+    let in_file = |e| Error::FileError {
+        filename: filename.clone(),
+        source: Box::new(e),
+    };
+    let text = std::fs::read_to_string(filepath).map_err(|e| in_file(e.into()))?;
 
-    parse_proto_text(text)
+    let mut proto = parse_proto_text(text).map_err(in_file)?;
+    proto.filename = filename;
+
+    Ok(proto)
 }
 
+/// Parses `text`, failing with the first error.
+///
+/// A grammar error is built by parsing again with pest's error detail, which names the expected
+/// tokens. That switch (`pest::set_error_detail`) is process-wide: it is turned on for the
+/// re-parse and off once no re-parse is running, overriding any value set by the caller.
 pub fn parse_proto_text(text: impl AsRef<str>) -> Result<ProtoFile> {
-    let mut proto: ProtoFile = Default::default();
-    let file = ProtoParser::parse(Rule::file, text.as_ref())?;
+    let text = text.as_ref();
+    let pairs = parse_rule(Rule::file, text)?;
+    let (proto, errors) = proto_file_from_pairs(text, pairs);
 
-    for entry in file {
-        match entry.as_rule() {
-            Rule::syntax => {}
-            Rule::import => {
-                let import = parse_import(entry)?;
-                proto.import.push(import);
-            }
-            Rule::package => {
-                let package = entry
-                    .into_inner()
-                    .next()
-                    .ok_or_else(|| token_not_found("packageName"))?;
-                proto.package = package.as_str().to_string();
-            }
-            Rule::option => {
-                let opt = parse_option(entry)?;
-                proto.options.push(opt);
-            }
-            Rule::emptyStatement => {}
-            Rule::topLevelDef => {
-                for part in entry.into_inner() {
-                    match part.as_rule() {
-                        Rule::Message => {
-                            let msg = parse_message(part)?;
-                            proto.messages.push(msg);
-                        }
-                        Rule::Enum => {
-                            let e = parse_enum(part)?;
-                            proto.enums.push(e);
-                        }
-                        Rule::Service => {
-                            let s = parse_service(part)?;
-                            proto.services.push(s);
-                        }
-                        _ => return Err(unexpect_token(part)),
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(proto),
+    }
+}
+
+fn parse_file_item(
+    entry: Pair<'_, Rule>,
+    proto: &mut ProtoFile,
+    first: bool,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    match entry.as_rule() {
+        Rule::syntax => {
+            proto.syntax = parse_syntax(entry)?;
+        }
+        Rule::edition => {
+            proto.syntax = parse_edition(entry)?;
+        }
+        Rule::import => {
+            let import = parse_import(entry)?;
+            proto.import.push(import);
+        }
+        Rule::package => {
+            proto.package_span = entry.as_span().into();
+            let package = first_child(entry, "packageName")?;
+            proto.package = package.as_str().to_string();
+        }
+        Rule::option => {
+            let opt = parse_option(entry)?;
+            proto.options.push(opt);
+        }
+        Rule::emptyStatement => {}
+        Rule::topLevelDef => {
+            for part in entry.into_inner() {
+                match part.as_rule() {
+                    Rule::Message => {
+                        let msg = parse_message(part, errors)?;
+                        proto.messages.push(msg);
                     }
+                    Rule::Enum => {
+                        let e = parse_enum(part, errors)?;
+                        proto.enums.push(e);
+                    }
+                    Rule::Extend => {
+                        let e = parse_extend(part, errors)?;
+                        proto.extends.push(e);
+                    }
+                    Rule::Service => {
+                        let s = parse_service(part, errors)?;
+                        proto.services.push(s);
+                    }
+                    _ => return Err(unexpect_token(part)),
                 }
             }
-            Rule::EOI => {}
-            _ => return Err(unexpect_token(entry)),
         }
+        // `syntax` and `edition` may only come first
+        Rule::skipped if first => return Err(skipped_statement(entry, Rule::firstFileStatement)),
+        Rule::skipped => return Err(skipped_statement(entry, Rule::fileStatement)),
+        Rule::strayBrace => return Err(unexpect_token(entry)),
+        Rule::EOI => {}
+        _ => return Err(unexpect_token(entry)),
     }
 
-    Ok(proto)
+    Ok(())
+}
+
+/// Describes a statement the grammar skipped, by matching it again as a `statement` of its
+/// enclosing block, on its own.
+fn skipped_statement(pair: Pair<'_, Rule>, statement: Rule) -> Error {
+    let text = pair.get_input();
+    let start = pair.as_span().start();
+
+    match parse_detailed(statement, &text[start..]) {
+        Err(e) => grammar_error(e, text, start),
+        Ok(_) => unexpect_token(pair),
+    }
+}
+
+/// Builds the model from the pairs of a `Rule::file` match of `text`.
+///
+/// Statements that fail, whether the grammar skipped them or their values are invalid, are
+/// left out of the model and returned as errors, in source order. Every error is located, i.e.
+/// has a `diagnostic()`.
+pub(crate) fn proto_file_from_pairs(text: &str, file: Pairs<'_, Rule>) -> (ProtoFile, Vec<Error>) {
+    let mut proto: ProtoFile = Default::default();
+    let mut errors = Vec::new();
+
+    for (i, entry) in file.enumerate() {
+        let span = entry.as_span();
+        let first_error = errors.len();
+        if let Err(e) = parse_file_item(entry, &mut proto, i == 0, &mut errors) {
+            errors.push(e);
+        }
+        // errors without a location of their own point at their top-level statement
+        for e in errors[first_error..].iter_mut() {
+            if e.diagnostic().is_none() {
+                *e = Error::GrammarError {
+                    message: e.to_string(),
+                    location: span.into(),
+                };
+            }
+        }
+    }
+
+    for m in proto.messages.iter_mut() {
+        check_labels(proto.syntax, m, &mut errors);
+    }
+    for extend in proto.extends.iter_mut() {
+        check_field_labels(proto.syntax, &mut extend.fields, &mut errors);
+    }
+    if proto.syntax == Syntax::Proto3 {
+        for m in proto.messages.iter_mut() {
+            assign_synthetic_oneofs(m);
+        }
+        for extend in proto.extends.iter_mut() {
+            for f in extend.fields.iter_mut() {
+                f.proto3_optional = f.label == Label::Optional;
+            }
+        }
+    }
+    crate::features::resolve(&mut proto, &mut errors);
+    crate::comments::attach(text, &mut proto);
+    // features are resolved after the whole file is built, so their errors come last
+    errors.sort_by_cached_key(|e| e.diagnostic().map(|d| d.span.byte_range.start));
+
+    (proto, errors)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::features::*;
 
     #[test]
     fn test_parser() {
@@ -471,6 +1160,869 @@ mod test {
         println!("proto: {:?}", proto);
     }
 
+    #[test]
+    fn test_parse_reserved() {
+        let text = r#"syntax = "proto3";
+                    message Foo {
+                        reserved 2, 15, 9 to 11;
+                        reserved 40 to max;
+                        reserved "foo", "bar";
+                        int32 baz = 1;
+                    }
+                    enum Bar {
+                        reserved 3, 5 to max;
+                        reserved "QUX";
+                        ZERO = 0;
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let msg = &proto.messages[0];
+        assert_eq!(
+            msg.reserved_ranges,
+            vec![
+                ReservedRange { start: 2, end: 2 },
+                ReservedRange { start: 15, end: 15 },
+                ReservedRange { start: 9, end: 11 },
+                ReservedRange {
+                    start: 40,
+                    end: MAX_FIELD_NUMBER
+                },
+            ]
+        );
+        assert_eq!(msg.reserved_names, vec!["foo", "bar"]);
+
+        let e = &proto.enums[0];
+        assert_eq!(
+            e.reserved_ranges,
+            vec![
+                ReservedRange { start: 3, end: 3 },
+                ReservedRange {
+                    start: 5,
+                    end: MAX_ENUM_VALUE
+                },
+            ]
+        );
+        assert_eq!(e.reserved_names, vec!["QUX"]);
+    }
+
+    #[test]
+    fn test_parse_field_options() {
+        let text = r#"syntax = "proto3";
+                    message Foo {
+                        int32 x = 1 [deprecated = true, json_name = "y"];
+                        map<string, int32> m = 2 [deprecated = true];
+                        oneof o {
+                            string s = 3 [(my_option).a = 1, packed = false];
+                        }
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let fields = &proto.messages[0].fields;
+        match &fields[0] {
+            MessageField::Normal(f) => {
+                let opts: Vec<_> = f
+                    .options
+                    .iter()
+                    .map(|o| (o.name.to_string(), o.raw_value.as_str()))
+                    .collect();
+                assert_eq!(
+                    opts,
+                    vec![
+                        ("deprecated".to_string(), "true"),
+                        ("json_name".to_string(), "\"y\"")
+                    ]
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &fields[1] {
+            MessageField::Map(f) => assert_eq!(f.options.len(), 1),
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &fields[2] {
+            MessageField::Oneof(o) => {
+                let names: Vec<_> = o.fields[0]
+                    .options
+                    .iter()
+                    .map(|o| o.name.to_string())
+                    .collect();
+                assert_eq!(names, vec!["(my_option).a", "packed"]);
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+    }
+
+    #[test]
+    fn test_parse_constant() {
+        let text = r#"syntax = "proto3";
+                    option java_package = "com.example.foo";
+                    option optimize_for = SPEED;
+                    option cc_enable_arenas = true;
+                    option (a) = -42;
+                    option (b) = 0x1F;
+                    option (c) = 017;
+                    option (d) = 1.5e3;
+                    option (e) = -inf;
+                    option (f) = "tab\there \"quoted\"";
+                    option (g) = -9223372036854775808;
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let values: Vec<_> = proto.options.iter().map(|o| o.value.clone()).collect();
+        assert_eq!(
+            values,
+            vec![
+                Constant::Str("com.example.foo".to_string()),
+                Constant::Ident("SPEED".to_string()),
+                Constant::Bool(true),
+                Constant::Int(-42),
+                Constant::Uint(31),
+                Constant::Uint(15),
+                Constant::Float(1500.0),
+                Constant::Float(f64::NEG_INFINITY),
+                Constant::Str("tab\there \"quoted\"".to_string()),
+                Constant::Int(i64::MIN),
+            ]
+        );
+        assert_eq!(proto.options[0].raw_value, "\"com.example.foo\"");
+    }
+
+    #[test]
+    fn test_parse_aggregate_option() {
+        let text = r#"syntax = "proto3";
+                    service Library {
+                        rpc GetBook(GetBookRequest) returns (Book) {
+                            option (google.api.http) = {
+                                get: "/v1/{name=shelves/*/books/*}"
+                                additional_bindings { post: "/v1/books" body: "*" }
+                            };
+                        }
+                    }
+                    option (a) = <
+                        ids: [1, -2, 0x3]
+                        tags: ["x", 'y'];
+                        mode: FAST,
+                        [ext.flag]: true
+                        [type.googleapis.com/foo.Bar] { v: 1.5 }
+                        items [{ n: 1 }, { n: 2 }]
+                    >;
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let http = match &proto.services[0].rpcs[0].options[0].value {
+            Constant::Aggregate(v) => v.clone(),
+            v => panic!("unexpected value {:?}", v),
+        };
+        assert_eq!(
+            http.get("get"),
+            Some(&AggregateFieldValue::Scalar(Constant::Str(
+                "/v1/{name=shelves/*/books/*}".to_string()
+            )))
+        );
+        match http.get("additional_bindings") {
+            Some(AggregateFieldValue::Message(binding)) => assert_eq!(
+                binding.get("body"),
+                Some(&AggregateFieldValue::Scalar(Constant::Str("*".to_string())))
+            ),
+            v => panic!("unexpected value {:?}", v),
+        }
+
+        let value = match &proto.options[0].value {
+            Constant::Aggregate(v) => v.clone(),
+            v => panic!("unexpected value {:?}", v),
+        };
+        assert_eq!(
+            value.get("ids"),
+            Some(&AggregateFieldValue::List(vec![
+                AggregateFieldValue::Scalar(Constant::Uint(1)),
+                AggregateFieldValue::Scalar(Constant::Int(-2)),
+                AggregateFieldValue::Scalar(Constant::Uint(3)),
+            ]))
+        );
+        assert!(value.fields[3].name.is_extension);
+        assert_eq!(
+            value.to_string(),
+            "{ ids: [1, -2, 3] tags: [\"x\", \"y\"] mode: FAST [ext.flag]: true \
+             [type.googleapis.com/foo.Bar] { v: 1.5 } items: [{ n: 1 }, { n: 2 }] }"
+        );
+    }
+
+    #[test]
+    fn test_parse_str_literals() {
+        let text = r#"syntax = 'proto2';
+                    import 'foo/bar.proto';
+                    option (a) = "multi" 'part'
+                        " string";
+                    option (b) = "\x00\xff\101";
+                    message Foo {
+                        reserved 'baz';
+                        optional string s = 1 [default = "caf\u00e9 \U0001F600"];
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        assert_eq!(proto.import[0].proto_file, "foo/bar.proto");
+        assert_eq!(
+            proto.options[0].value,
+            Constant::Str("multipart string".to_string())
+        );
+        assert_eq!(proto.options[1].value, Constant::Bytes(vec![0, 0xff, b'A']));
+        assert_eq!(proto.options[1].value.to_string(), "\"\\000\\377A\"");
+        assert_eq!(proto.messages[0].reserved_names, vec!["baz"]);
+        match &proto.messages[0].fields[0] {
+            MessageField::Normal(f) => assert_eq!(
+                f.options[0].value,
+                Constant::Str("caf\u{e9} \u{1f600}".to_string())
+            ),
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        match parse_proto_text("syntax = \"proto3\";\nimport \"\\xff\";") {
+            Err(Error::InvalidUtf8 { location, .. }) => assert_eq!(location.start, (2, 8)),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_parse_int_literals() {
+        let text = r#"syntax = "proto2";
+                    enum Foo {
+                        reserved -5 to -3, 0x20 to max;
+                        NEG = -1;
+                        HEX = 0x10;
+                        OCT = 017;
+                        MIN = -2147483648;
+                    }
+                    message Bar {
+                        optional int32 a = 0x1F [default = -0x10];
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let e = &proto.enums[0];
+        let values: Vec<_> = e.fields.iter().map(|f| f.value).collect();
+        assert_eq!(values, vec![-1, 16, 15, i32::MIN]);
+        assert_eq!(
+            e.reserved_ranges,
+            vec![
+                ReservedRange { start: -5, end: -3 },
+                ReservedRange {
+                    start: 32,
+                    end: MAX_ENUM_VALUE
+                },
+            ]
+        );
+        match &proto.messages[0].fields[0] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.number, 31);
+                assert_eq!(f.options[0].value, Constant::Int(-16));
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        for (text, literal, line_col) in [
+            ("enum E { A = 2147483648; }", "2147483648", (1, 14)),
+            (
+                "message M { int32 a = 0x100000000; }",
+                "0x100000000",
+                (1, 23),
+            ),
+            ("message M { reserved -1; }", "-1", (1, 22)),
+            (
+                "option (a) = 18446744073709551616;",
+                "18446744073709551616",
+                (1, 14),
+            ),
+        ] {
+            match parse_proto_text(text) {
+                Err(Error::IntegerOutOfRange {
+                    literal: l,
+                    location,
+                }) => {
+                    assert_eq!(l, literal);
+                    assert_eq!(location.start, line_col);
+                }
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_option_name() {
+        let text = r#"syntax = "proto3";
+                    option (my_option).a.b = true;
+                    option ( .foo.bar ).(baz.qux) = 1;
+                    option java_package = "x";
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let part = |name: &str, is_extension| OptionNamePart {
+            name: name.to_string(),
+            is_extension,
+        };
+        assert_eq!(
+            proto.options[0].name.parts,
+            vec![part("my_option", true), part("a", false), part("b", false)]
+        );
+        assert_eq!(
+            proto.options[1].name.parts,
+            vec![part(".foo.bar", true), part("baz.qux", true)]
+        );
+        assert_eq!(proto.options[1].name.to_string(), "(.foo.bar).(baz.qux)");
+        assert!(proto.options[2].name == "java_package");
+    }
+
+    #[test]
+    fn test_parse_rpc_streaming() {
+        let text = r#"syntax = "proto3";
+                    service Chat {
+                        rpc Unary(Req) returns (Resp);
+                        rpc Upload(stream Req) returns (Resp);
+                        rpc Watch(Req) returns (stream Resp) {}
+                        rpc Talk(stream Req) returns (stream Resp) {
+                            option deprecated = true;
+                        }
+                        rpc Named(streamReq) returns (Resp);
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let rpcs: Vec<_> = proto.services[0]
+            .rpcs
+            .iter()
+            .map(|r| (r.request.as_str(), r.client_streaming, r.server_streaming))
+            .collect();
+        assert_eq!(
+            rpcs,
+            vec![
+                ("Req", false, false),
+                ("Req", true, false),
+                ("Req", false, true),
+                ("Req", true, true),
+                ("streamReq", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_proto2() {
+        let text = r#"syntax = "proto2";
+                    message SearchResponse {
+                        required string query = 1;
+                        optional int32 page = 2 [default = 10];
+                        repeated group Result = 3 {
+                            required string url = 4;
+                            optional string title = 5;
+                        }
+                        optional optionalType other = 6;
+                        extensions 100 to 199, 1000 to max [(declaration) = true];
+                        oneof choice {
+                            string name = 7;
+                            group Pick = 8 { optional int32 id = 9; }
+                        }
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.syntax, Syntax::Proto2);
+
+        let msg = &proto.messages[0];
+        let labels: Vec<_> = msg
+            .fields
+            .iter()
+            .map(|f| match f {
+                MessageField::Normal(f) => (f.name.clone(), f.label),
+                MessageField::Group(g) => (g.field_name(), g.label),
+                MessageField::Oneof(o) => (o.name.clone(), Label::Implicit),
+                f => panic!("unexpected field {:?}", f),
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("query".to_string(), Label::Required),
+                ("page".to_string(), Label::Optional),
+                ("result".to_string(), Label::Repeated),
+                ("other".to_string(), Label::Optional),
+                ("choice".to_string(), Label::Implicit),
+            ]
+        );
+        match &msg.fields[1] {
+            MessageField::Normal(f) => assert_eq!(f.default_value(), Some(&Constant::Uint(10))),
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &msg.fields[2] {
+            MessageField::Group(g) => {
+                assert_eq!(g.number, 3);
+                assert_eq!(g.message.name, "Result");
+                assert_eq!(g.message.fields.len(), 2);
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        let ranges: Vec<_> = msg
+            .extension_ranges
+            .iter()
+            .map(|r| (r.start, r.end, r.options.len()))
+            .collect();
+        assert_eq!(ranges, vec![(100, 199, 1), (1000, MAX_FIELD_NUMBER, 1)]);
+        match &msg.fields[4] {
+            MessageField::Oneof(o) => {
+                let pick = &o.fields[1];
+                assert_eq!(pick.name, "pick");
+                assert_eq!(
+                    pick.field_type,
+                    NormalFieldType::MessageOrEnum("Pick".to_string())
+                );
+                assert_eq!(pick.group.as_ref().map(|m| m.fields.len()), Some(1));
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        let text = "syntax = \"proto2\";\nmessage A {\n  int32 a = 1;\n}\n";
+        match parse_proto_text(text) {
+            Err(e @ Error::InvalidLabel { .. }) => assert_eq!(
+                e.to_string(),
+                "3:3: Expected \"required\", \"optional\", or \"repeated\"."
+            ),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let text = "syntax = \"proto2\";\nmessage A {\n  oneof o { optional group G = 1 {} }\n}\n";
+        assert!(matches!(
+            parse_proto_text(text),
+            Err(Error::InvalidLabel { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_descriptor_proto() {
+        let proto = parse_proto_file("./protos/google/protobuf/descriptor.proto")
+            .expect("parse descriptor.proto failed");
+        assert_eq!(proto.syntax, Syntax::Proto2);
+        assert_eq!(proto.package, "google.protobuf");
+        assert_eq!(proto.messages.len(), 21);
+
+        let field = proto
+            .messages
+            .iter()
+            .find(|m| m.name == "FieldDescriptorProto")
+            .expect("FieldDescriptorProto not found");
+        let enums: Vec<_> = field.inner_enums.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(enums, vec!["Type", "Label"]);
+
+        let file_options = proto
+            .messages
+            .iter()
+            .find(|m| m.name == "FileOptions")
+            .expect("FileOptions not found");
+        let range = &file_options.extension_ranges[0];
+        assert_eq!((range.start, range.end), (1000, MAX_FIELD_NUMBER));
+        let optimize_for = file_options
+            .fields
+            .iter()
+            .find_map(|f| match f {
+                MessageField::Normal(f) if f.name == "optimize_for" => Some(f),
+                _ => None,
+            })
+            .expect("optimize_for not found");
+        assert_eq!(optimize_for.label, Label::Optional);
+        assert_eq!(
+            optimize_for.default_value(),
+            Some(&Constant::Ident("SPEED".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_syntax() {
+        let proto = parse_proto_text("message Foo {}").expect("parse proto text failed");
+        assert_eq!(proto.syntax, Syntax::Proto2);
+
+        let proto = parse_proto_text(r#"syntax = "proto3";"#).expect("parse proto text failed");
+        assert_eq!(proto.syntax, Syntax::Proto3);
+
+        match parse_proto_text(r#"syntax = "proto4";"#) {
+            Err(Error::UnsupportedSyntax { syntax, .. }) => assert_eq!(syntax, "proto4"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_parse_edition_features() {
+        let text = r#"edition = "2023";
+                    option features.enum_type = CLOSED;
+                    enum Kind {
+                        option features.enum_type = OPEN;
+                        KIND_UNSPECIFIED = 0;
+                    }
+                    message Foo {
+                        option features.field_presence = IMPLICIT;
+                        int32 a = 1;
+                        int32 b = 2 [features.field_presence = EXPLICIT];
+                        repeated int32 c = 3 [features.repeated_field_encoding = EXPANDED];
+                        oneof pick {
+                            option features.enum_type = CLOSED;
+                            Inner d = 4;
+                        }
+                        enum Inner {
+                            INNER_UNSPECIFIED = 0;
+                        }
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.edition(), Some(Edition::Edition2023));
+        assert_eq!(proto.features.enum_type, EnumType::Closed);
+        assert_eq!(proto.enums[0].features.enum_type, EnumType::Open);
+
+        let msg = &proto.messages[0];
+        assert_eq!(msg.features.field_presence, FieldPresence::Implicit);
+        assert_eq!(msg.inner_enums[0].features.enum_type, EnumType::Closed);
+        let features: Vec<_> = msg
+            .fields
+            .iter()
+            .map(|f| match f {
+                MessageField::Normal(f) => f.features,
+                MessageField::Oneof(o) => {
+                    assert_eq!(o.features.enum_type, EnumType::Closed);
+                    o.fields[0].features
+                }
+                f => panic!("unexpected field {:?}", f),
+            })
+            .collect();
+        assert_eq!(features[0].field_presence, FieldPresence::Implicit);
+        assert_eq!(features[1].field_presence, FieldPresence::Explicit);
+        assert_eq!(
+            features[2].repeated_field_encoding,
+            RepeatedFieldEncoding::Expanded
+        );
+        assert_eq!(features[3].enum_type, EnumType::Closed);
+
+        match parse_proto_text(r#"edition = "2023"; option features.enum_type = SOMETIMES;"#) {
+            Err(Error::InvalidFeatureValue { feature, .. }) => assert_eq!(feature, "enum_type"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse_proto_text(r#"edition = "2023"; message A { optional int32 a = 1; }"#) {
+            Err(Error::InvalidLabel { message, .. }) => {
+                assert!(message.starts_with("Label \"optional\" is not supported in editions."))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse_proto_text(r#"edition = "2023"; extend A { required int32 a = 1; }"#) {
+            Err(Error::InvalidLabel { message, .. }) => {
+                assert!(message.starts_with("Label \"required\" is not supported in editions,"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match parse_proto_text(r#"edition = "1999";"#) {
+            Err(Error::UnsupportedEdition { edition, .. }) => assert_eq!(edition, "1999"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_parse_legacy_features() {
+        let text = r#"syntax = "proto3";
+                    message Foo {
+                        int32 a = 1;
+                        repeated int32 b = 2 [packed = false];
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.features, FeatureSet::defaults(Syntax::Proto3));
+        match &proto.messages[0].fields[1] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.features.field_presence, FieldPresence::Implicit);
+                assert_eq!(
+                    f.features.repeated_field_encoding,
+                    RepeatedFieldEncoding::Expanded
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        let text = r#"syntax = "proto2";
+                    message Foo {
+                        required int32 a = 1;
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.features.enum_type, EnumType::Closed);
+        match &proto.messages[0].fields[0] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.features.field_presence, FieldPresence::LegacyRequired)
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+    }
+
+    #[test]
+    fn test_parse_extend() {
+        let text = r#"syntax = "proto2";
+                    import "google/protobuf/descriptor.proto";
+                    extend google.protobuf.MessageOptions {
+                        optional string my_option = 51234;
+                        repeated int32 my_list = 51235 [packed = true];
+                    }
+                    message Foo {
+                        extensions 100 to 199;
+                        extend Foo {
+                            optional int32 bar = 126;
+                        }
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let extend = &proto.extends[0];
+        assert_eq!(extend.extendee, "google.protobuf.MessageOptions");
+        let fields: Vec<_> = extend
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.number, f.label))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("my_option", 51234, Label::Optional),
+                ("my_list", 51235, Label::Repeated),
+            ]
+        );
+        assert_eq!(
+            extend.fields[1].features.repeated_field_encoding,
+            RepeatedFieldEncoding::Packed
+        );
+
+        let extend = &proto.messages[0].extends[0];
+        assert_eq!(extend.extendee, "Foo");
+        assert_eq!(extend.fields[0].name, "bar");
+    }
+
+    #[test]
+    fn test_parse_proto3_optional() {
+        let text = r#"syntax = "proto3";
+                    message Foo {
+                        optional int32 foo = 1;
+                        int32 bar = 2;
+                        optional string _foo = 3;
+                        oneof _baz {
+                            int32 qux = 4;
+                        }
+                        optional int32 baz = 5;
+                        message Inner {
+                            optional bool flag = 1;
+                        }
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let msg = &proto.messages[0];
+        assert_eq!(msg.synthetic_oneofs(), vec!["X_foo", "XX_foo", "X_baz"]);
+        match &msg.fields[0] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.label, Label::Optional);
+                assert!(f.proto3_optional);
+                assert_eq!(f.features.field_presence, FieldPresence::Explicit);
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        match &msg.fields[1] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.label, Label::Implicit);
+                assert!(!f.proto3_optional);
+                assert_eq!(f.synthetic_oneof, None);
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+        assert_eq!(msg.inner_messages[0].synthetic_oneofs(), vec!["_flag"]);
+
+        let text = r#"syntax = "proto3";
+                    message Foo {
+                        oneof o {
+                            int32 _x = 1;
+                        }
+                        optional int32 x = 2;
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.messages[0].synthetic_oneofs(), vec!["X_x"]);
+
+        let text = r#"syntax = "proto2";
+                    message Foo {
+                        optional int32 foo = 1;
+                    }
+                    "#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert!(proto.messages[0].synthetic_oneofs().is_empty());
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let text = "syntax = \"proto3\";\n\
+                    import \"a.proto\";\n\
+                    message Foo {\n  \
+                      int32 bar = 1 [deprecated = true];\n\
+                    }\n\
+                    service S {\n  \
+                      rpc Call(Foo) returns (Foo);\n\
+                    }\n";
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let import = &proto.import[0].span;
+        assert_eq!(&text[import.byte_range.clone()], "import \"a.proto\";");
+        assert_eq!(import.start, (2, 1));
+
+        let msg = &proto.messages[0];
+        assert_eq!(msg.span.start, (3, 1));
+        assert_eq!(msg.span.end, (5, 2));
+        match &msg.fields[0] {
+            MessageField::Normal(f) => {
+                assert_eq!(f.span.start, (4, 3));
+                assert_eq!(
+                    &text[f.span.byte_range.clone()],
+                    "int32 bar = 1 [deprecated = true];"
+                );
+                assert_eq!(
+                    &text[f.options[0].span.byte_range.clone()],
+                    "deprecated = true"
+                );
+            }
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        let rpc = &proto.services[0].rpcs[0];
+        assert_eq!(rpc.span.start, (7, 3));
+        assert_eq!(rpc.span.end, (7, 31));
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let text = r#"syntax = "proto3";
+
+// detached one
+
+/* detached /* nested */ two */
+
+// Leading for Foo
+// spans two lines.
+message Foo { // trailing for Foo
+  /* Leading for bar
+   * continued */
+  int32 bar = 1; // trailing for bar
+  int32 baz = 2;
+  // trailing for baz
+
+  // detached inside Foo
+
+  oneof choice {
+    string a = 3;
+  } // not attached to a
+}
+
+enum E {
+  // leading for ZERO
+  ZERO = 0; /* trailing for ZERO */
+}
+
+service S {
+  // leading for Call
+  rpc Call(Foo) returns (Foo); // trailing for Call
+}
+"#;
+
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+
+        let msg = &proto.messages[0];
+        assert_eq!(
+            msg.comments,
+            Comments {
+                leading: Some(" Leading for Foo\n spans two lines.\n".to_string()),
+                trailing: Some(" trailing for Foo\n".to_string()),
+                detached: vec![
+                    " detached one\n".to_string(),
+                    " detached /* nested */ two ".to_string()
+                ],
+            }
+        );
+        let comments: Vec<_> = msg
+            .fields
+            .iter()
+            .map(|f| match f {
+                MessageField::Normal(f) => f.comments.clone(),
+                MessageField::Oneof(o) => o.comments.clone(),
+                f => panic!("unexpected field {:?}", f),
+            })
+            .collect();
+        assert_eq!(
+            comments[0].leading.as_deref(),
+            Some(" Leading for bar\n continued ")
+        );
+        assert_eq!(comments[0].trailing.as_deref(), Some(" trailing for bar\n"));
+        assert_eq!(comments[1].trailing.as_deref(), Some(" trailing for baz\n"));
+        assert_eq!(comments[2].leading, None);
+        assert_eq!(comments[2].detached, vec![" detached inside Foo\n"]);
+        match &msg.fields[2] {
+            MessageField::Oneof(o) => assert_eq!(o.fields[0].comments, Comments::default()),
+            f => panic!("unexpected field {:?}", f),
+        }
+
+        let zero = &proto.enums[0].fields[0].comments;
+        assert_eq!(zero.leading.as_deref(), Some(" leading for ZERO\n"));
+        assert_eq!(zero.trailing.as_deref(), Some(" trailing for ZERO "));
+
+        let call = &proto.services[0].rpcs[0].comments;
+        assert_eq!(call.leading.as_deref(), Some(" leading for Call\n"));
+        assert_eq!(call.trailing.as_deref(), Some(" trailing for Call\n"));
+    }
+
+    #[test]
+    fn test_parse_proto_file_errors() {
+        let path = std::env::temp_dir().join(format!("proto3-parser-{}.proto", std::process::id()));
+        std::fs::write(
+            &path,
+            "syntax = \"proto3\";\nmessage A {\n  int32 a = 1\n}\n",
+        )
+        .expect("write failed");
+        let filename = path.to_string_lossy().into_owned();
+
+        let err = parse_proto_file(&path).expect_err("parse should fail");
+        std::fs::remove_file(&path).expect("remove_file failed");
+        match &err {
+            Error::FileError {
+                filename: f,
+                source,
+            } => {
+                assert_eq!(f, &filename);
+                assert!(matches!(**source, Error::GrammarError { .. }));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        let diagnostic = err.diagnostic().expect("diagnostic missing");
+        assert_eq!(diagnostic.filename, filename);
+        assert_eq!(diagnostic.span.start, (4, 1));
+
+        match parse_proto_file(&path) {
+            Err(Error::FileError { source, .. }) => {
+                assert!(matches!(*source, Error::IoError { .. }))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
     #[test]
     fn test_parse_dir_files() {
         let dir = std::fs::read_dir("./protos").expect("read_dir failed");
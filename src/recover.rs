@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use crate::diagnostic::Diagnostic;
+use crate::error::*;
+use crate::model::ProtoFile;
+use crate::parser::{parse_rule, proto_file_from_pairs, Rule};
+
+/// Parses as much of `text` as possible, reporting every error instead of stopping at the first.
+///
+/// Statements that fail to parse are skipped up to the next `;` or the end of their block, and
+/// blocks left open at the end of the text are closed. The text is matched once; the grammar
+/// itself skips what it cannot match. Describing a skipped statement re-parses it with pest's
+/// process-wide error detail, as described on `parse_proto_text`.
+pub fn parse_proto_text_recovering(text: impl AsRef<str>) -> (ProtoFile, Vec<Diagnostic>) {
+    let text = text.as_ref();
+    let pairs = match parse_rule(Rule::file, text) {
+        Ok(pairs) => pairs,
+        // not expected, as `skipped` accepts any statement
+        Err(e) => return (Default::default(), e.diagnostic().into_iter().collect()),
+    };
+    let (proto, errors) = proto_file_from_pairs(text, pairs);
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    // every error of `proto_file_from_pairs` is located
+    for diagnostic in errors.iter().filter_map(Error::diagnostic) {
+        // nested blocks left open all end at the end of the text
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    (proto, diagnostics)
+}
+
+/// Reads `filepath` and parses it like `parse_proto_text_recovering`, naming the file in the
+/// model and the diagnostics. Only failing to read the file is an error.
+pub fn parse_proto_file_recovering(
+    filepath: impl AsRef<Path>,
+) -> Result<(ProtoFile, Vec<Diagnostic>)> {
+    let filepath = filepath.as_ref();
+    let filename = filepath.to_string_lossy().into_owned();
+    let text = std::fs::read_to_string(filepath).map_err(|e| Error::FileError {
+        filename: filename.clone(),
+        source: Box::new(e.into()),
+    })?;
+
+    let (mut proto, diagnostics) = parse_proto_text_recovering(text);
+    let diagnostics = diagnostics
+        .into_iter()
+        .map(|d| d.filename(filename.as_str()))
+        .collect();
+    proto.filename = filename;
+
+    Ok((proto, diagnostics))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Syntax;
+
+    #[test]
+    fn test_recover() {
+        let text = r#"syntax = "proto3";
+            foo bar;
+            message A {
+                int32 a = 1
+                string b = 2;
+                int32 = 3;
+                message {}
+                repeated string "c;}" = 4;
+                int64 d = 0x100000000;
+            }
+            enum E { ZERO = 0; ONE = 1 }
+            service S {
+                rpc Get(A) returns A;
+                rpc Put(A) returns (A);
+            }
+            message B {
+                int32 x = 1;
+            "#;
+
+        let (proto, diagnostics) = parse_proto_text_recovering(text);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.span.start.0, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (2, "Expected \";\", \"enum\", \"extend\", \"import\", \"message\", \"option\", \"package\" or \"service\", found \"foo\"."),
+                (5, "Expected \";\" or \"[\", found \"string\"."),
+                (6, "Expected identifier, found \"=\"."),
+                (7, "Expected identifier, found \"{\"."),
+                (8, "Expected identifier, found \"\\\"\"."),
+                (9, "Integer out of range."),
+                (11, "Expected \";\" or \"[\", found \"}\"."),
+                (13, "Expected \"(\", found \"A\"."),
+                (18, "Expected \".\", \";\", identifier or \"}\", found end of file."),
+            ]
+        );
+
+        assert_eq!(proto.syntax, Syntax::Proto3);
+        let names: Vec<_> = proto.messages.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert!(proto.messages[0].fields.is_empty());
+        assert_eq!(proto.messages[1].fields.len(), 1);
+        assert_eq!(proto.messages[1].span.start, (16, 13));
+        assert_eq!(proto.enums[0].fields.len(), 1);
+        assert_eq!(proto.services[0].rpcs[0].name, "Put");
+
+        let (proto, diagnostics) =
+            parse_proto_text_recovering("syntax = \"proto3\";\nmessage A {}\n");
+        assert!(diagnostics.is_empty());
+        assert_eq!(proto.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_recover_invalid_values() {
+        let text = r#"edition = "2023";
+            message A { int32 a = 1; }
+            option features.field_presence = BOGUS;
+            option (note) = "\ud800";
+            message B {}
+            "#;
+
+        let (proto, diagnostics) = parse_proto_text_recovering(text);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.span.start, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    (3, 13),
+                    "Invalid value \"BOGUS\" for feature \"field_presence\"."
+                ),
+                ((4, 30), "Invalid escape sequence \"\\ud800\"."),
+            ]
+        );
+
+        let names: Vec<_> = proto.messages.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert_eq!(proto.messages[0].fields.len(), 1);
+        assert!(proto.options.is_empty());
+    }
+
+    #[test]
+    fn test_recover_file() {
+        let path = std::env::temp_dir().join(format!(
+            "proto3-parser-recover-{}.proto",
+            std::process::id()
+        ));
+        std::fs::write(&path, "syntax = \"proto3\";\nmessage A { int32 a = 1 }\n")
+            .expect("write failed");
+        let filename = path.to_string_lossy().into_owned();
+
+        let result = parse_proto_file_recovering(&path);
+        std::fs::remove_file(&path).expect("remove_file failed");
+        let (proto, diagnostics) = result.expect("read failed");
+        assert_eq!(proto.filename, filename);
+        assert_eq!(proto.messages.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].filename, filename);
+        assert_eq!(diagnostics[0].span.start, (2, 25));
+
+        match parse_proto_file_recovering(&path) {
+            Err(Error::FileError { filename: f, .. }) => assert_eq!(f, filename),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
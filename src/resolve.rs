@@ -0,0 +1,614 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::*;
+use crate::loader::FileSet;
+use crate::model::*;
+
+/// A resolved reference to a message or enum, by fully qualified name with a leading dot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeRef {
+    Message(String),
+    Enum(String),
+}
+
+impl TypeRef {
+    pub fn name(&self) -> &str {
+        match self {
+            TypeRef::Message(name) | TypeRef::Enum(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    Package,
+    Message,
+    Enum,
+    Service,
+    Other,
+}
+
+impl SymbolKind {
+    fn is_aggregate(self) -> bool {
+        self != SymbolKind::Other
+    }
+
+    fn is_type(self) -> bool {
+        self == SymbolKind::Message || self == SymbolKind::Enum
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    kind: SymbolKind,
+    filename: String,
+}
+
+/// Symbol table over a `FileSet`, applying protobuf scoping rules to type names.
+#[derive(Debug, Clone, Default)]
+pub struct Resolver {
+    symbols: HashMap<String, Symbol>,
+    visible: HashMap<String, HashSet<String>>,
+}
+
+fn join(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+impl Resolver {
+    pub fn new(set: &FileSet) -> Result<Resolver> {
+        let mut resolver: Resolver = Default::default();
+
+        for proto in set.files() {
+            let visible = set
+                .visible_files(&proto.filename)
+                .iter()
+                .map(|p| p.filename.clone())
+                .collect();
+            resolver.visible.insert(proto.filename.clone(), visible);
+
+            let mut package = String::new();
+            for part in proto.package.split('.').filter(|p| !p.is_empty()) {
+                package = join(&package, part);
+                resolver.define(
+                    &package,
+                    SymbolKind::Package,
+                    &proto.filename,
+                    &proto.package_span,
+                )?;
+            }
+            for e in proto.enums.iter() {
+                resolver.define_enum(&package, e, &proto.filename)?;
+            }
+            for m in proto.messages.iter() {
+                resolver.define_message(&package, m, &proto.filename)?;
+            }
+            for extend in proto.extends.iter() {
+                for f in extend.fields.iter() {
+                    resolver.define(
+                        &join(&package, &f.name),
+                        SymbolKind::Other,
+                        &proto.filename,
+                        &f.span,
+                    )?;
+                }
+            }
+            for s in proto.services.iter() {
+                let name = join(&package, &s.name);
+                resolver.define(&name, SymbolKind::Service, &proto.filename, &s.span)?;
+                for rpc in s.rpcs.iter() {
+                    resolver.define(
+                        &join(&name, &rpc.name),
+                        SymbolKind::Other,
+                        &proto.filename,
+                        &rpc.span,
+                    )?;
+                }
+            }
+        }
+
+        Ok(resolver)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        filename: &str,
+        location: &Span,
+    ) -> Result<()> {
+        if let Some(symbol) = self.symbols.get(name) {
+            if symbol.kind == SymbolKind::Package && kind == SymbolKind::Package {
+                return Ok(());
+            }
+            return Err(Error::DuplicateSymbol {
+                name: name.to_string(),
+                defined_in: symbol.filename.clone(),
+                filename: filename.to_string(),
+                location: location.clone(),
+            });
+        }
+        self.symbols.insert(
+            name.to_string(),
+            Symbol {
+                kind,
+                filename: filename.to_string(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn define_enum(&mut self, scope: &str, e: &Enum, filename: &str) -> Result<()> {
+        self.define(&join(scope, &e.name), SymbolKind::Enum, filename, &e.span)?;
+        // enum values are siblings of their enum, not children of it
+        for field in e.fields.iter() {
+            self.define(
+                &join(scope, &field.name),
+                SymbolKind::Other,
+                filename,
+                &field.span,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn define_message(&mut self, scope: &str, msg: &Message, filename: &str) -> Result<()> {
+        let name = join(scope, &msg.name);
+        self.define(&name, SymbolKind::Message, filename, &msg.span)?;
+
+        for field in msg.fields.iter() {
+            match field {
+                MessageField::Normal(f) => {
+                    self.define(&join(&name, &f.name), SymbolKind::Other, filename, &f.span)?
+                }
+                MessageField::Map(f) => {
+                    self.define(&join(&name, &f.name), SymbolKind::Other, filename, &f.span)?
+                }
+                MessageField::Oneof(o) => {
+                    self.define(&join(&name, &o.name), SymbolKind::Other, filename, &o.span)?;
+                    for f in o.fields.iter() {
+                        self.define(&join(&name, &f.name), SymbolKind::Other, filename, &f.span)?;
+                        if let Some(m) = &f.group {
+                            self.define_message(&name, m, filename)?;
+                        }
+                    }
+                }
+                MessageField::Group(g) => {
+                    self.define(
+                        &join(&name, &g.field_name()),
+                        SymbolKind::Other,
+                        filename,
+                        &g.span,
+                    )?;
+                    self.define_message(&name, &g.message, filename)?;
+                }
+                MessageField::Invalid => {}
+            }
+        }
+        for e in msg.inner_enums.iter() {
+            self.define_enum(&name, e, filename)?;
+        }
+        for m in msg.inner_messages.iter() {
+            self.define_message(&name, m, filename)?;
+        }
+        for extend in msg.extends.iter() {
+            for f in extend.fields.iter() {
+                self.define(&join(&name, &f.name), SymbolKind::Other, filename, &f.span)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<(&str, &Symbol)> {
+        self.symbols
+            .get_key_value(name)
+            .map(|(name, symbol)| (name.as_str(), symbol))
+    }
+
+    // Packages span files, so they are visible everywhere.
+    fn is_visible(&self, filename: &str, symbol: &Symbol) -> bool {
+        match self.visible.get(filename) {
+            Some(files) => symbol.kind == SymbolKind::Package || files.contains(&symbol.filename),
+            None => true,
+        }
+    }
+
+    // Symbols of files `filename` does not import are skipped, the first of them is only
+    // returned when nothing visible resolves.
+    fn lookup(
+        &self,
+        filename: &str,
+        scope: &str,
+        name: &str,
+    ) -> std::result::Result<(String, &Symbol), Option<String>> {
+        if let Some(absolute) = name.strip_prefix('.') {
+            return match self.find(absolute) {
+                Some((name, symbol)) => Ok((name.to_string(), symbol)),
+                None => Err(None),
+            };
+        }
+
+        let first = name.split('.').next().unwrap_or(name);
+        let mut hidden = None;
+        let mut scope = Some(scope);
+        while let Some(current) = scope {
+            let candidate = join(current, first);
+            if let Some((_, symbol)) = self.find(&candidate) {
+                if first.len() < name.len() {
+                    if symbol.kind.is_aggregate() {
+                        let full = join(current, name);
+                        match self.find(&full) {
+                            Some((_, symbol)) if self.is_visible(filename, symbol) => {
+                                return Ok((full, symbol))
+                            }
+                            Some((_, symbol)) => {
+                                hidden = hidden.or(Some((full, symbol)));
+                            }
+                            None if self.is_visible(filename, symbol) => return Err(Some(full)),
+                            None => {}
+                        }
+                    }
+                } else if symbol.kind.is_type() {
+                    if self.is_visible(filename, symbol) {
+                        return Ok((candidate, symbol));
+                    }
+                    hidden = hidden.or(Some((candidate, symbol)));
+                }
+            }
+            scope = if current.is_empty() {
+                None
+            } else {
+                Some(current.rfind('.').map_or("", |i| &current[..i]))
+            };
+        }
+
+        hidden.ok_or(None)
+    }
+
+    /// Resolves `name` as written in `filename` inside the scope `scope` (e.g. `pkg.Outer`).
+    pub fn resolve(
+        &self,
+        filename: &str,
+        scope: &str,
+        name: &str,
+        location: &Span,
+    ) -> Result<TypeRef> {
+        let (full, symbol) = match self.lookup(filename, scope, name) {
+            Ok(found) => found,
+            Err(Some(resolved)) => {
+                return Err(Error::AmbiguousType {
+                    name: name.to_string(),
+                    resolved,
+                    location: location.clone(),
+                })
+            }
+            Err(None) => {
+                return Err(Error::UnknownType {
+                    name: name.to_string(),
+                    location: location.clone(),
+                })
+            }
+        };
+
+        if !self.is_visible(filename, symbol) {
+            return Err(Error::TypeNotImported {
+                name: name.to_string(),
+                defined_in: symbol.filename.clone(),
+                importer: filename.to_string(),
+                location: location.clone(),
+            });
+        }
+
+        match symbol.kind {
+            SymbolKind::Message => Ok(TypeRef::Message(format!(".{}", full))),
+            SymbolKind::Enum => Ok(TypeRef::Enum(format!(".{}", full))),
+            _ => Err(Error::NotAType {
+                name: name.to_string(),
+                location: location.clone(),
+            }),
+        }
+    }
+
+    fn resolve_message_type(
+        &self,
+        filename: &str,
+        scope: &str,
+        name: &str,
+        location: &Span,
+    ) -> Result<String> {
+        match self.resolve(filename, scope, name, location)? {
+            TypeRef::Message(name) => Ok(name),
+            TypeRef::Enum(_) => Err(Error::NotAMessageType {
+                name: name.to_string(),
+                location: location.clone(),
+            }),
+        }
+    }
+
+    fn link_field_type(
+        &self,
+        filename: &str,
+        scope: &str,
+        field_type: &mut NormalFieldType,
+        location: &Span,
+    ) -> Result<()> {
+        if let NormalFieldType::MessageOrEnum(name) = field_type {
+            *field_type = match self.resolve(filename, scope, name, location)? {
+                TypeRef::Message(name) => NormalFieldType::Message(name),
+                TypeRef::Enum(name) => NormalFieldType::Enum(name),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn link_extend(&self, filename: &str, scope: &str, extend: &mut Extend) -> Result<()> {
+        extend.extendee =
+            self.resolve_message_type(filename, scope, &extend.extendee, &extend.span)?;
+        for f in extend.fields.iter_mut() {
+            self.link_field_type(filename, scope, &mut f.field_type, &f.span)?;
+        }
+
+        Ok(())
+    }
+
+    fn link_message(&self, filename: &str, scope: &str, msg: &mut Message) -> Result<()> {
+        let scope = join(scope, &msg.name);
+
+        for field in msg.fields.iter_mut() {
+            match field {
+                MessageField::Normal(f) => {
+                    self.link_field_type(filename, &scope, &mut f.field_type, &f.span)?
+                }
+                MessageField::Map(f) => {
+                    self.link_field_type(filename, &scope, &mut f.value_type, &f.span)?
+                }
+                MessageField::Oneof(o) => {
+                    for f in o.fields.iter_mut() {
+                        self.link_field_type(filename, &scope, &mut f.field_type, &f.span)?;
+                        if let Some(m) = &mut f.group {
+                            self.link_message(filename, &scope, m)?;
+                        }
+                    }
+                }
+                MessageField::Group(g) => self.link_message(filename, &scope, &mut g.message)?,
+                MessageField::Invalid => {}
+            }
+        }
+        for m in msg.inner_messages.iter_mut() {
+            self.link_message(filename, &scope, m)?;
+        }
+        for extend in msg.extends.iter_mut() {
+            self.link_extend(filename, &scope, extend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites every type reference of `proto` to its fully qualified name.
+    pub fn link_file(&self, proto: &mut ProtoFile) -> Result<()> {
+        let filename = proto.filename.clone();
+        let package = proto.package.clone();
+
+        for m in proto.messages.iter_mut() {
+            self.link_message(&filename, &package, m)?;
+        }
+        for extend in proto.extends.iter_mut() {
+            self.link_extend(&filename, &package, extend)?;
+        }
+        for s in proto.services.iter_mut() {
+            let scope = join(&package, &s.name);
+            for rpc in s.rpcs.iter_mut() {
+                rpc.request =
+                    self.resolve_message_type(&filename, &scope, &rpc.request, &rpc.span)?;
+                rpc.response =
+                    self.resolve_message_type(&filename, &scope, &rpc.response, &rpc.span)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves every type reference in `set` to its fully qualified name.
+pub fn link(set: &mut FileSet) -> Result<()> {
+    let resolver = Resolver::new(set)?;
+
+    for proto in set.files_mut() {
+        resolver.link_file(proto)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_proto_text;
+
+    fn file_set(files: &[(&str, &str)]) -> FileSet {
+        let mut set: FileSet = Default::default();
+        for (name, text) in files {
+            let mut proto = parse_proto_text(text).expect("parse proto text failed");
+            proto.filename = name.to_string();
+            set.insert(proto);
+        }
+        set
+    }
+
+    fn field_type(field: &MessageField) -> &NormalFieldType {
+        match field {
+            MessageField::Normal(f) => &f.field_type,
+            f => panic!("unexpected field {:?}", f),
+        }
+    }
+
+    fn message(name: &str) -> NormalFieldType {
+        NormalFieldType::Message(name.to_string())
+    }
+
+    #[test]
+    fn test_link() {
+        let mut set = file_set(&[
+            (
+                "common.proto",
+                r#"syntax = "proto3";
+                package foo.common;
+                message Id { string value = 1; }
+                enum Kind { KIND_UNSPECIFIED = 0; }
+                "#,
+            ),
+            (
+                "svc.proto",
+                r#"syntax = "proto3";
+                package foo.bar;
+                import "common.proto";
+                message Outer {
+                    message Inner {
+                        Outer outer = 1;
+                    }
+                    Inner inner = 1;
+                    common.Id id = 2;
+                    .foo.common.Kind kind = 3;
+                    map<string, Outer.Inner> inners = 4;
+                }
+                service Svc {
+                    rpc Get(common.Id) returns (Outer);
+                }
+                "#,
+            ),
+        ]);
+
+        link(&mut set).expect("link failed");
+
+        let proto = set.get("svc.proto").unwrap();
+        let outer = &proto.messages[0];
+        assert_eq!(
+            field_type(&outer.inner_messages[0].fields[0]),
+            &message(".foo.bar.Outer")
+        );
+        assert_eq!(
+            field_type(&outer.fields[0]),
+            &message(".foo.bar.Outer.Inner")
+        );
+        assert_eq!(field_type(&outer.fields[1]), &message(".foo.common.Id"));
+        assert_eq!(
+            field_type(&outer.fields[2]),
+            &NormalFieldType::Enum(".foo.common.Kind".to_string())
+        );
+        assert_eq!(
+            field_type(&outer.fields[2]).wire_type(),
+            Some(WireType::Varint)
+        );
+        assert_eq!(
+            field_type(&outer.fields[1]).wire_type(),
+            Some(WireType::LengthDelimited)
+        );
+        match &outer.fields[3] {
+            MessageField::Map(f) => assert_eq!(f.value_type, message(".foo.bar.Outer.Inner")),
+            f => panic!("unexpected field {:?}", f),
+        }
+        let rpc = &proto.services[0].rpcs[0];
+        assert_eq!(rpc.request, ".foo.common.Id");
+        assert_eq!(rpc.response, ".foo.bar.Outer");
+
+        let resolver = Resolver::new(&set).expect("resolver failed");
+        let location = Span::default();
+        assert_eq!(
+            resolver
+                .resolve("svc.proto", "foo.bar", "common.Kind", &location)
+                .unwrap(),
+            TypeRef::Enum(".foo.common.Kind".to_string())
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let set = file_set(&[
+            ("a.proto", "syntax = \"proto3\"; package a; message A {}"),
+            (
+                "b.proto",
+                r#"syntax = "proto3";
+                package b;
+                message B {
+                    message a {}
+                    a.A x = 1;
+                }
+                enum E { E_ZERO = 0; }
+                "#,
+            ),
+        ]);
+        let resolver = Resolver::new(&set).expect("resolver failed");
+        let location = Span::default();
+
+        match resolver.resolve("b.proto", "b.B", "a.A", &location) {
+            Err(Error::AmbiguousType { resolved, .. }) => assert_eq!(resolved, "b.B.a.A"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match resolver.resolve("b.proto", "b.B", ".a.A", &location) {
+            Err(Error::TypeNotImported { defined_in, .. }) => assert_eq!(defined_in, "a.proto"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match resolver.resolve("b.proto", "b.B", "Missing", &location) {
+            Err(Error::UnknownType { name, .. }) => assert_eq!(name, "Missing"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match resolver.resolve("b.proto", "b.B", "E_ZERO", &location) {
+            Err(Error::UnknownType { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let set = file_set(&[
+            (
+                "hidden.proto",
+                "syntax = \"proto3\"; package o.i; message T {}",
+            ),
+            ("top.proto", "syntax = \"proto3\"; package o; message T {}"),
+            (
+                "use.proto",
+                "syntax = \"proto3\"; package o.i; import \"top.proto\"; message U {}",
+            ),
+        ]);
+        let resolver = Resolver::new(&set).expect("resolver failed");
+        match resolver.resolve("use.proto", "o.i.U", "T", &location) {
+            Ok(TypeRef::Message(name)) => assert_eq!(name, ".o.T"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match resolver.resolve("top.proto", "o", "i.T", &location) {
+            Err(Error::TypeNotImported { defined_in, .. }) => {
+                assert_eq!(defined_in, "hidden.proto")
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let set = file_set(&[
+            ("a.proto", "syntax = \"proto3\"; package p; message A {}"),
+            ("b.proto", "syntax = \"proto3\";\npackage p;\nmessage A {}"),
+        ]);
+        let err = Resolver::new(&set).expect_err("duplicate symbol not reported");
+        match &err {
+            Error::DuplicateSymbol {
+                name,
+                defined_in,
+                filename,
+                ..
+            } => {
+                assert_eq!(name, "p.A");
+                assert_eq!(defined_in, "a.proto");
+                assert_eq!(filename, "b.proto");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            err.diagnostic().map(|d| d.to_string()).as_deref(),
+            Some("b.proto:3:1: error: \"p.A\" is already defined in file \"a.proto\".")
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::features::EnumType;
+use crate::model::*;
+
+const FIRST_RESERVED_NUMBER: u32 = 19_000;
+const LAST_RESERVED_NUMBER: u32 = 19_999;
+
+fn join(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn error(&mut self, span: &Span, message: String) {
+        self.diagnostics
+            .push(Diagnostic::error(message, span.clone()));
+    }
+
+    fn check_field_number(&mut self, number: u32, span: &Span) {
+        if number == 0 {
+            self.error(span, "Field numbers must be positive integers.".to_string());
+        } else if number > MAX_FIELD_NUMBER as u32 {
+            self.error(
+                span,
+                format!("Field numbers cannot be greater than {}.", MAX_FIELD_NUMBER),
+            );
+        } else if (FIRST_RESERVED_NUMBER..=LAST_RESERVED_NUMBER).contains(&number) {
+            self.error(
+                span,
+                format!(
+                    "Field numbers {} through {} are reserved for the protocol buffer library implementation.",
+                    FIRST_RESERVED_NUMBER, LAST_RESERVED_NUMBER
+                ),
+            );
+        }
+    }
+
+    fn check_extend(&mut self, extend: &Extend) {
+        for f in extend.fields.iter() {
+            self.check_field_number(f.number, &f.span);
+        }
+    }
+
+    fn check_message(&mut self, scope: &str, msg: &Message) {
+        let full_name = join(scope, &msg.name);
+        let mut fields: Vec<(String, u32, &Span)> = Vec::new();
+
+        for field in msg.fields.iter() {
+            match field {
+                MessageField::Normal(f) => fields.push((f.name.clone(), f.number, &f.span)),
+                MessageField::Map(f) => fields.push((f.name.clone(), f.number, &f.span)),
+                MessageField::Oneof(o) => {
+                    for f in o.fields.iter() {
+                        fields.push((f.name.clone(), f.number, &f.span));
+                        if let Some(m) = &f.group {
+                            self.check_message(&full_name, m);
+                        }
+                    }
+                }
+                MessageField::Group(g) => {
+                    fields.push((g.field_name(), g.number, &g.span));
+                    self.check_message(&full_name, &g.message);
+                }
+                MessageField::Invalid => {}
+            }
+        }
+
+        let mut used: HashMap<u32, String> = HashMap::new();
+        for (name, number, span) in fields {
+            self.check_field_number(number, span);
+            if let Some(previous) = used.get(&number) {
+                self.error(
+                    span,
+                    format!(
+                        "Field number {} has already been used in \"{}\" by field \"{}\".",
+                        number, full_name, previous
+                    ),
+                );
+            } else {
+                used.insert(number, name.clone());
+            }
+            let reserved = msg
+                .reserved_ranges
+                .iter()
+                .any(|r| r.contains(number as i32));
+            if number <= MAX_FIELD_NUMBER as u32 && reserved {
+                self.error(
+                    span,
+                    format!("Field \"{}\" uses reserved number {}.", name, number),
+                );
+            }
+        }
+
+        self.check_scope(&full_name, &msg.inner_messages, &msg.inner_enums);
+        for m in msg.inner_messages.iter() {
+            self.check_message(&full_name, m);
+        }
+        for e in msg.inner_enums.iter() {
+            self.check_enum(&full_name, e);
+        }
+        for extend in msg.extends.iter() {
+            self.check_extend(extend);
+        }
+    }
+
+    /// Types and enum values share one namespace, since enum values are siblings of their enum.
+    fn check_scope(&mut self, scope: &str, messages: &[Message], enums: &[Enum]) {
+        // (name, enclosing enum for enum values, span)
+        let mut symbols: Vec<(&str, Option<&str>, &Span)> = Vec::new();
+        symbols.extend(messages.iter().map(|m| (m.name.as_str(), None, &m.span)));
+        for e in enums.iter() {
+            symbols.push((&e.name, None, &e.span));
+            symbols.extend(
+                e.fields
+                    .iter()
+                    .map(|v| (v.name.as_str(), Some(e.name.as_str()), &v.span)),
+            );
+        }
+        symbols.sort_by_key(|(_, _, span)| span.start);
+
+        let mut defined: HashMap<&str, Option<&str>> = HashMap::new();
+        for (name, owner, span) in symbols {
+            let previous = match defined.get(name) {
+                Some(previous) => *previous,
+                None => {
+                    defined.insert(name, owner);
+                    continue;
+                }
+            };
+            match owner {
+                Some(owner) if previous != Some(owner) => {
+                    let within = if scope.is_empty() {
+                        "the global scope".to_string()
+                    } else {
+                        format!("\"{}\"", scope)
+                    };
+                    self.error(
+                        span,
+                        format!(
+                            "{} Note that enum values use C++ scoping rules, meaning that enum values are siblings of their type, not children of it.  Therefore, \"{}\" must be unique within {}, not just within \"{}\".",
+                            already_defined(scope, name),
+                            name,
+                            within,
+                            owner
+                        ),
+                    );
+                }
+                _ => self.error(span, already_defined(scope, name)),
+            }
+        }
+    }
+
+    fn check_enum(&mut self, scope: &str, e: &Enum) {
+        if e.features.enum_type == EnumType::Open {
+            if let Some(first) = e.fields.first().filter(|v| v.value != 0) {
+                self.error(
+                    &first.span,
+                    "The first enum value must be zero for open enums.".to_string(),
+                );
+            }
+        }
+
+        let allow_alias = e
+            .options
+            .iter()
+            .any(|o| o.name == "allow_alias" && o.value == Constant::Bool(true));
+        let mut used: HashMap<i32, &str> = HashMap::new();
+        let mut has_alias = false;
+        for v in e.fields.iter() {
+            if let Some(previous) = used.get(&v.value) {
+                has_alias = true;
+                if !allow_alias {
+                    self.error(
+                        &v.span,
+                        format!(
+                            "\"{}\" uses the same enum value as \"{}\". If this is intended, set 'option allow_alias = true;' to the enum definition.",
+                            join(scope, &v.name),
+                            join(scope, previous)
+                        ),
+                    );
+                }
+            } else {
+                used.insert(v.value, &v.name);
+            }
+            if e.reserved_ranges.iter().any(|r| r.contains(v.value)) {
+                self.error(
+                    &v.span,
+                    format!(
+                        "Enum value \"{}\" uses reserved number {}.",
+                        v.name, v.value
+                    ),
+                );
+            }
+            if e.reserved_names.contains(&v.name) {
+                self.error(&v.span, format!("Enum value \"{}\" is reserved.", v.name));
+            }
+        }
+        if allow_alias && !has_alias {
+            self.error(
+                &e.span,
+                format!(
+                    "Enum \"{}\" declares support for enum aliases but no enum values share field numbers. Please remove the unnecessary 'option allow_alias = true;' declaration.",
+                    join(scope, &e.name)
+                ),
+            );
+        }
+    }
+}
+
+fn already_defined(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        format!("\"{}\" is already defined.", name)
+    } else {
+        format!("\"{}\" is already defined in \"{}\".", name, scope)
+    }
+}
+
+/// Checks the rules protoc enforces after parsing, in source order.
+pub fn validate(proto: &ProtoFile) -> Vec<Diagnostic> {
+    let mut validator: Validator = Default::default();
+
+    validator.check_scope(&proto.package, &proto.messages, &proto.enums);
+    for m in proto.messages.iter() {
+        validator.check_message(&proto.package, m);
+    }
+    for e in proto.enums.iter() {
+        validator.check_enum(&proto.package, e);
+    }
+    for extend in proto.extends.iter() {
+        validator.check_extend(extend);
+    }
+
+    let mut diagnostics: Vec<_> = validator
+        .diagnostics
+        .into_iter()
+        .map(|d| d.filename(proto.filename.as_str()))
+        .collect();
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_proto_text;
+
+    fn messages(text: &str) -> Vec<String> {
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        validate(&proto).iter().map(|d| d.message.clone()).collect()
+    }
+
+    #[test]
+    fn test_validate_field_numbers() {
+        let errors = messages(
+            r#"syntax = "proto3";
+            package foo;
+            message Foo {
+                reserved 5 to 7;
+                int32 a = 0;
+                int32 b = 536870912;
+                int32 c = 19000;
+                int32 d = 1;
+                map<string, int32> e = 1;
+                oneof choice { int32 f = 6; }
+                message Inner { int32 g = 1; int32 h = 1; }
+            }
+            "#,
+        );
+
+        assert_eq!(
+            errors,
+            vec![
+                "Field numbers must be positive integers.",
+                "Field numbers cannot be greater than 536870911.",
+                "Field numbers 19000 through 19999 are reserved for the protocol buffer library implementation.",
+                "Field number 1 has already been used in \"foo.Foo\" by field \"d\".",
+                "Field \"f\" uses reserved number 6.",
+                "Field number 1 has already been used in \"foo.Foo.Inner\" by field \"g\".",
+            ]
+        );
+
+        let proto = parse_proto_text("syntax = \"proto3\";\nmessage A {\n  int32 a = 0;\n}\n")
+            .expect("parse proto text failed");
+        assert_eq!(
+            validate(&proto)[0].to_string(),
+            "3:3: error: Field numbers must be positive integers."
+        );
+        assert!(messages("syntax = \"proto3\";\nmessage A { int32 a = 1; }").is_empty());
+
+        let errors = messages(
+            r#"syntax = "proto2";
+            message Foo {
+                reserved 3;
+                optional group Result = 1 { optional int32 a = 1; }
+                optional int32 b = 1;
+                optional group Extra = 3 {}
+            }
+            "#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Field number 1 has already been used in \"Foo\" by field \"result\".",
+                "Field \"extra\" uses reserved number 3.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_enums() {
+        let errors = messages(
+            r#"syntax = "proto3";
+            package foo;
+            enum Color {
+                reserved 3;
+                reserved "PURPLE";
+                RED = 1;
+                GREEN = 1;
+                BLUE = 3;
+                PURPLE = 4;
+            }
+            enum Light {
+                option allow_alias = true;
+                OFF = 0;
+                RED = 2;
+            }
+            message Holder {
+                enum Kind { A = 0; B = 1; A = 2; }
+                message Kind {}
+            }
+            "#,
+        );
+
+        assert_eq!(
+            errors,
+            vec![
+                "The first enum value must be zero for open enums.",
+                "\"foo.GREEN\" uses the same enum value as \"foo.RED\". If this is intended, set 'option allow_alias = true;' to the enum definition.",
+                "Enum value \"BLUE\" uses reserved number 3.",
+                "Enum value \"PURPLE\" is reserved.",
+                "Enum \"foo.Light\" declares support for enum aliases but no enum values share field numbers. Please remove the unnecessary 'option allow_alias = true;' declaration.",
+                "\"RED\" is already defined in \"foo\". Note that enum values use C++ scoping rules, meaning that enum values are siblings of their type, not children of it.  Therefore, \"RED\" must be unique within \"foo\", not just within \"Light\".",
+                "\"A\" is already defined in \"foo.Holder\".",
+                "\"Kind\" is already defined in \"foo.Holder\".",
+            ]
+        );
+
+        assert!(messages(
+            r#"syntax = "proto2";
+            enum Closed { option allow_alias = true; ONE = 1; UNO = 1; }
+            "#
+        )
+        .is_empty());
+    }
+}
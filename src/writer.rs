@@ -0,0 +1,658 @@
+use std::fmt;
+
+use crate::descriptor::c_escape;
+use crate::model::*;
+
+/// Order in which `ProtoWriter` emits the declarations of a scope.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeclOrder {
+    /// Keep the order of the source text.
+    #[default]
+    Source,
+    /// Options, reserved, extensions, fields, enums, messages, extends, then services.
+    Grouped,
+    /// Like `Grouped`, with enums, messages, services and rpcs sorted by name.
+    Alphabetical,
+}
+
+/// Formats a `ProtoFile` back into `.proto` source text.
+#[derive(Debug, Clone)]
+pub struct ProtoWriter {
+    indent: String,
+    order: DeclOrder,
+    comments: bool,
+}
+
+impl Default for ProtoWriter {
+    fn default() -> ProtoWriter {
+        ProtoWriter {
+            indent: "  ".to_string(),
+            order: DeclOrder::Source,
+            comments: true,
+        }
+    }
+}
+
+impl ProtoWriter {
+    pub fn new() -> ProtoWriter {
+        Default::default()
+    }
+
+    pub fn indent(mut self, indent: impl Into<String>) -> ProtoWriter {
+        self.indent = indent.into();
+        self
+    }
+
+    pub fn order(mut self, order: DeclOrder) -> ProtoWriter {
+        self.order = order;
+        self
+    }
+
+    pub fn comments(mut self, comments: bool) -> ProtoWriter {
+        self.comments = comments;
+        self
+    }
+
+    pub fn write(&self, proto: &ProtoFile) -> String {
+        let mut out = String::new();
+        // Writing to a String never fails.
+        let _ = self.write_to(proto, &mut out);
+        out
+    }
+
+    pub fn write_to<W: fmt::Write>(&self, proto: &ProtoFile, out: &mut W) -> fmt::Result {
+        let mut printer = Printer {
+            config: self,
+            out,
+            depth: 0,
+            syntax: proto.syntax,
+        };
+
+        printer.file(proto)
+    }
+}
+
+impl fmt::Display for ProtoFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ProtoWriter::default().write_to(self, f)
+    }
+}
+
+enum Item<'a> {
+    Option(&'a ProtoOption),
+    Reserved {
+        ranges: &'a [ReservedRange],
+        names: &'a [String],
+        max: i32,
+        anchor: (usize, usize),
+    },
+    Extensions(&'a ExtensionRange, (usize, usize)),
+    Field(&'a MessageField),
+    Value(&'a EnumField),
+    Rpc(&'a RPC),
+    Enum(&'a Enum),
+    Message(&'a Message),
+    Extend(&'a Extend),
+    Service(&'a Service),
+}
+
+impl Item<'_> {
+    // Items without a span of their own are placed after the options of their scope.
+    fn start(&self) -> (usize, usize) {
+        match self {
+            Item::Option(o) => o.span.start,
+            Item::Reserved { anchor, .. } | Item::Extensions(_, anchor) => *anchor,
+            Item::Field(MessageField::Normal(f)) => f.span.start,
+            Item::Field(MessageField::Map(f)) => f.span.start,
+            Item::Field(MessageField::Oneof(o)) => o.span.start,
+            Item::Field(MessageField::Group(g)) => g.span.start,
+            Item::Field(MessageField::Invalid) => (0, 0),
+            Item::Value(v) => v.span.start,
+            Item::Rpc(r) => r.span.start,
+            Item::Enum(e) => e.span.start,
+            Item::Message(m) => m.span.start,
+            Item::Extend(e) => e.span.start,
+            Item::Service(s) => s.span.start,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Item::Option(_) => 0,
+            Item::Reserved { .. } => 1,
+            Item::Extensions(..) => 2,
+            Item::Field(_) | Item::Value(_) | Item::Rpc(_) => 3,
+            Item::Enum(_) => 4,
+            Item::Message(_) => 5,
+            Item::Extend(_) => 6,
+            Item::Service(_) => 7,
+        }
+    }
+
+    // Sort key for `DeclOrder::Alphabetical`, fields and values keep their order.
+    fn name(&self) -> &str {
+        match self {
+            Item::Rpc(r) => &r.name,
+            Item::Enum(e) => &e.name,
+            Item::Message(m) => &m.name,
+            Item::Extend(e) => &e.extendee,
+            Item::Service(s) => &s.name,
+            _ => "",
+        }
+    }
+
+    fn is_block(&self) -> bool {
+        self.rank() > 3
+    }
+}
+
+fn label(label: Label) -> &'static str {
+    match label {
+        Label::Implicit => "",
+        Label::Optional => "optional ",
+        Label::Repeated => "repeated ",
+        Label::Required => "required ",
+    }
+}
+
+fn option_value(opt: &ProtoOption) -> String {
+    match opt.raw_value.trim() {
+        "" => opt.value.to_string(),
+        raw => raw.to_string(),
+    }
+}
+
+fn field_options(options: &[ProtoOption]) -> String {
+    if options.is_empty() {
+        return String::new();
+    }
+    let options: Vec<_> = options
+        .iter()
+        .map(|o| format!("{} = {}", o.name, option_value(o)))
+        .collect();
+
+    format!(" [{}]", options.join(", "))
+}
+
+fn range(start: i32, end: i32, max: i32) -> String {
+    if start == end {
+        start.to_string()
+    } else if end == max {
+        format!("{} to max", start)
+    } else {
+        format!("{} to {}", start, end)
+    }
+}
+
+fn message_items(msg: &Message) -> Vec<Item<'_>> {
+    let anchor = msg
+        .options
+        .iter()
+        .map(|o| o.span.start)
+        .max()
+        .unwrap_or_default();
+    let mut items: Vec<_> = msg.options.iter().map(Item::Option).collect();
+    if !msg.reserved_ranges.is_empty() || !msg.reserved_names.is_empty() {
+        items.push(Item::Reserved {
+            ranges: &msg.reserved_ranges,
+            names: &msg.reserved_names,
+            max: MAX_FIELD_NUMBER,
+            anchor,
+        });
+    }
+    items.extend(
+        msg.extension_ranges
+            .iter()
+            .map(|r| Item::Extensions(r, anchor)),
+    );
+    items.extend(msg.fields.iter().map(Item::Field));
+    items.extend(msg.inner_enums.iter().map(Item::Enum));
+    items.extend(msg.inner_messages.iter().map(Item::Message));
+    items.extend(msg.extends.iter().map(Item::Extend));
+
+    items
+}
+
+struct Printer<'a, W> {
+    config: &'a ProtoWriter,
+    out: &'a mut W,
+    depth: usize,
+    syntax: Syntax,
+}
+
+impl<W: fmt::Write> Printer<'_, W> {
+    fn line(&mut self, text: &str) -> fmt::Result {
+        if !text.is_empty() {
+            for _ in 0..self.depth {
+                self.out.write_str(&self.config.indent)?;
+            }
+            self.out.write_str(text)?;
+        }
+        self.out.write_char('\n')
+    }
+
+    fn comment(&mut self, text: &str) -> fmt::Result {
+        for line in text.strip_suffix('\n').unwrap_or(text).split('\n') {
+            self.line(format!("//{}", line).trim_end())?;
+        }
+        Ok(())
+    }
+
+    // Writes one line with its comments, `block` lines open a `{ ... }` closed by `close`.
+    fn head(&mut self, text: &str, comments: &Comments, block: bool) -> fmt::Result {
+        let mut trailing = None;
+        if self.config.comments {
+            for detached in comments.detached.iter() {
+                self.comment(detached)?;
+                self.line("")?;
+            }
+            if let Some(leading) = &comments.leading {
+                self.comment(leading)?;
+            }
+            trailing = comments
+                .trailing
+                .as_deref()
+                .map(|c| c.strip_suffix('\n').unwrap_or(c));
+        }
+
+        let text = if block {
+            format!("{} {{", text)
+        } else {
+            text.to_string()
+        };
+        match trailing {
+            Some(c) if !c.contains('\n') => self.line(&format!("{} //{}", text, c.trim_end()))?,
+            _ => self.line(&text)?,
+        }
+        if block {
+            self.depth += 1;
+        }
+        match trailing {
+            Some(c) if c.contains('\n') => self.comment(c),
+            _ => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> fmt::Result {
+        self.depth -= 1;
+        self.line("}")
+    }
+
+    fn block(&mut self, text: &str, comments: &Comments, items: Vec<Item<'_>>) -> fmt::Result {
+        if items.is_empty() {
+            return self.head(&format!("{} {{}}", text), comments, false);
+        }
+        self.head(text, comments, true)?;
+        self.items(items)?;
+        self.close()
+    }
+
+    fn items(&mut self, mut items: Vec<Item<'_>>) -> fmt::Result {
+        match self.config.order {
+            DeclOrder::Source => items.sort_by_key(|i| (i.start(), i.rank())),
+            DeclOrder::Grouped => items.sort_by_key(Item::rank),
+            DeclOrder::Alphabetical => {
+                items.sort_by(|a, b| a.rank().cmp(&b.rank()).then_with(|| a.name().cmp(b.name())))
+            }
+        }
+
+        let mut prev: Option<&Item<'_>> = None;
+        for item in items.iter() {
+            if let Some(prev) = prev {
+                if prev.is_block() || item.is_block() || prev.rank() != item.rank() {
+                    self.line("")?;
+                }
+            }
+            self.item(item)?;
+            prev = Some(item);
+        }
+
+        Ok(())
+    }
+
+    fn item(&mut self, item: &Item<'_>) -> fmt::Result {
+        match item {
+            Item::Option(o) => self.line(&format!("option {} = {};", o.name, option_value(o))),
+            Item::Reserved {
+                ranges, names, max, ..
+            } => self.reserved(ranges, names, *max),
+            Item::Extensions(r, _) => self.line(&format!(
+                "extensions {}{};",
+                range(r.start, r.end, MAX_FIELD_NUMBER),
+                field_options(&r.options)
+            )),
+            Item::Field(f) => self.field(f),
+            Item::Value(v) => self.head(
+                &format!("{} = {}{};", v.name, v.value, field_options(&v.options)),
+                &v.comments,
+                false,
+            ),
+            Item::Rpc(r) => self.rpc(r),
+            Item::Enum(e) => self.enumeration(e),
+            Item::Message(m) => self.message(m),
+            Item::Extend(e) => self.extend(e),
+            Item::Service(s) => self.service(s),
+        }
+    }
+
+    fn reserved(&mut self, ranges: &[ReservedRange], names: &[String], max: i32) -> fmt::Result {
+        if !ranges.is_empty() {
+            let ranges: Vec<_> = ranges.iter().map(|r| range(r.start, r.end, max)).collect();
+            self.line(&format!("reserved {};", ranges.join(", ")))?;
+        }
+        if !names.is_empty() {
+            let names: Vec<_> = match self.syntax {
+                Syntax::Edition(_) => names.to_vec(),
+                _ => names
+                    .iter()
+                    .map(|n| format!("\"{}\"", c_escape(n.as_bytes())))
+                    .collect(),
+            };
+            self.line(&format!("reserved {};", names.join(", ")))?;
+        }
+        Ok(())
+    }
+
+    fn normal_field(&mut self, f: &NormalField) -> fmt::Result {
+        let text = format!(
+            "{}{} {} = {}{};",
+            label(f.label),
+            f.field_type,
+            f.name,
+            f.number,
+            field_options(&f.options)
+        );
+        self.head(&text, &f.comments, false)
+    }
+
+    fn field(&mut self, field: &MessageField) -> fmt::Result {
+        match field {
+            MessageField::Normal(f) => self.normal_field(f),
+            MessageField::Map(f) => {
+                let text = format!(
+                    "map<{}, {}> {} = {}{};",
+                    f.key_type,
+                    f.value_type,
+                    f.name,
+                    f.number,
+                    field_options(&f.options)
+                );
+                self.head(&text, &f.comments, false)
+            }
+            MessageField::Oneof(o) => {
+                self.head(&format!("oneof {}", o.name), &o.comments, true)?;
+                self.items(o.options.iter().map(Item::Option).collect())?;
+                for f in o.fields.iter() {
+                    if let Some(m) = &f.group {
+                        let text = format!(
+                            "group {} = {}{}",
+                            m.name,
+                            f.number,
+                            field_options(&f.options)
+                        );
+                        self.block(&text, &f.comments, message_items(m))?;
+                        continue;
+                    }
+                    let text = format!(
+                        "{} {} = {}{};",
+                        f.field_type,
+                        f.name,
+                        f.number,
+                        field_options(&f.options)
+                    );
+                    self.head(&text, &f.comments, false)?;
+                }
+                self.close()
+            }
+            MessageField::Group(g) => {
+                let text = format!(
+                    "{}group {} = {}{}",
+                    label(g.label),
+                    g.name,
+                    g.number,
+                    field_options(&g.options)
+                );
+                self.block(&text, &g.comments, message_items(&g.message))
+            }
+            MessageField::Invalid => Ok(()),
+        }
+    }
+
+    fn enumeration(&mut self, e: &Enum) -> fmt::Result {
+        let anchor = e
+            .options
+            .iter()
+            .map(|o| o.span.start)
+            .max()
+            .unwrap_or_default();
+        let mut items: Vec<_> = e.options.iter().map(Item::Option).collect();
+        if !e.reserved_ranges.is_empty() || !e.reserved_names.is_empty() {
+            items.push(Item::Reserved {
+                ranges: &e.reserved_ranges,
+                names: &e.reserved_names,
+                max: MAX_ENUM_VALUE,
+                anchor,
+            });
+        }
+        items.extend(e.fields.iter().map(Item::Value));
+
+        self.block(&format!("enum {}", e.name), &e.comments, items)
+    }
+
+    fn message(&mut self, msg: &Message) -> fmt::Result {
+        let text = format!("message {}", msg.name);
+        self.block(&text, &msg.comments, message_items(msg))
+    }
+
+    fn extend(&mut self, extend: &Extend) -> fmt::Result {
+        self.head(
+            &format!("extend {}", extend.extendee),
+            &Default::default(),
+            true,
+        )?;
+        for f in extend.fields.iter() {
+            self.normal_field(f)?;
+        }
+        self.close()
+    }
+
+    fn rpc(&mut self, rpc: &RPC) -> fmt::Result {
+        let stream = |streaming: bool| if streaming { "stream " } else { "" };
+        let text = format!(
+            "rpc {}({}{}) returns ({}{})",
+            rpc.name,
+            stream(rpc.client_streaming),
+            rpc.request,
+            stream(rpc.server_streaming),
+            rpc.response
+        );
+
+        if rpc.options.is_empty() {
+            return self.head(&format!("{};", text), &rpc.comments, false);
+        }
+        self.block(
+            &text,
+            &rpc.comments,
+            rpc.options.iter().map(Item::Option).collect(),
+        )
+    }
+
+    fn service(&mut self, s: &Service) -> fmt::Result {
+        let mut items: Vec<_> = s.options.iter().map(Item::Option).collect();
+        items.extend(s.rpcs.iter().map(Item::Rpc));
+
+        self.block(&format!("service {}", s.name), &s.comments, items)
+    }
+
+    fn file(&mut self, proto: &ProtoFile) -> fmt::Result {
+        match proto.syntax {
+            Syntax::Proto2 => self.line("syntax = \"proto2\";")?,
+            Syntax::Proto3 => self.line("syntax = \"proto3\";")?,
+            Syntax::Edition(Edition::Edition2023) => self.line("edition = \"2023\";")?,
+            Syntax::Edition(Edition::Edition2024) => self.line("edition = \"2024\";")?,
+        }
+        if !proto.package.is_empty() {
+            self.line("")?;
+            self.line(&format!("package {};", proto.package))?;
+        }
+        if !proto.import.is_empty() {
+            self.line("")?;
+            for import in proto.import.iter() {
+                let kind = match import.import_type {
+                    ImportType::Normal => "",
+                    ImportType::Weak => "weak ",
+                    ImportType::Public => "public ",
+                };
+                self.line(&format!(
+                    "import {}\"{}\";",
+                    kind,
+                    c_escape(import.proto_file.as_bytes())
+                ))?;
+            }
+        }
+        if !proto.options.is_empty() {
+            self.line("")?;
+            self.items(proto.options.iter().map(Item::Option).collect())?;
+        }
+
+        let mut items: Vec<_> = proto.enums.iter().map(Item::Enum).collect();
+        items.extend(proto.messages.iter().map(Item::Message));
+        items.extend(proto.extends.iter().map(Item::Extend));
+        items.extend(proto.services.iter().map(Item::Service));
+        if !items.is_empty() {
+            self.line("")?;
+            self.items(items)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_proto_text;
+
+    const TEXT: &str = r#"syntax="proto3";
+package   foo.bar ;
+import public "other.proto";
+option java_package="com.foo";
+service Svc { rpc Get ( Req ) returns ( stream Resp ) ; // get
+  rpc Put(Req) returns (Resp) { option deprecated = true; } }
+
+// Detached.
+
+// A request.
+message Req {
+  reserved 2, 9 to max; reserved "old";
+  optional string name=1 [json_name="n", deprecated=true];
+  map<string,Req> children = 3;
+  oneof choice { option (tag) = "c"; int32 id = 4; Kind kind = 5; }
+  enum Kind { option allow_alias = true; A = 0; B = 0; }
+  message Inner {}
+}
+message Resp {}
+"#;
+
+    #[test]
+    fn test_write() {
+        let proto = parse_proto_text(TEXT).expect("parse proto text failed");
+
+        let expected = r#"syntax = "proto3";
+
+package foo.bar;
+
+import public "other.proto";
+
+option java_package = "com.foo";
+
+service Svc {
+  rpc Get(Req) returns (stream Resp); // get
+  rpc Put(Req) returns (Resp) {
+    option deprecated = true;
+  }
+}
+
+// Detached.
+
+// A request.
+message Req {
+  reserved 2, 9 to max;
+  reserved "old";
+
+  optional string name = 1 [json_name = "n", deprecated = true];
+  map<string, Req> children = 3;
+  oneof choice {
+    option (tag) = "c";
+    int32 id = 4;
+    Kind kind = 5;
+  }
+
+  enum Kind {
+    option allow_alias = true;
+
+    A = 0;
+    B = 0;
+  }
+
+  message Inner {}
+}
+
+message Resp {}
+"#;
+        assert_eq!(proto.to_string(), expected);
+
+        let reparsed = parse_proto_text(expected).expect("parse written text failed");
+        assert_eq!(reparsed.to_string(), expected);
+    }
+
+    #[test]
+    fn test_write_ordering() {
+        let proto = parse_proto_text(TEXT).expect("parse proto text failed");
+
+        let text = ProtoWriter::new()
+            .indent("\t")
+            .order(DeclOrder::Alphabetical)
+            .comments(false)
+            .write(&proto);
+        let decls: Vec<_> = text
+            .lines()
+            .filter(|l| !l.starts_with('\t') && l.contains('{'))
+            .collect();
+        assert_eq!(
+            decls,
+            vec!["message Req {", "message Resp {}", "service Svc {"]
+        );
+        assert!(text.contains("\n\tenum Kind {\n\t\toption allow_alias = true;\n"));
+        assert!(!text.contains("//"));
+    }
+
+    #[test]
+    fn test_write_string_escapes() {
+        let value = Constant::Str("a\u{85}\u{1}\u{e9}\n".to_string());
+        let proto = ProtoFile {
+            options: vec![ProtoOption {
+                name: OptionName {
+                    parts: vec![OptionNamePart {
+                        name: "java_package".to_string(),
+                        is_extension: false,
+                    }],
+                },
+                value: value.clone(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let text = ProtoWriter::new().write(&proto);
+        assert!(text.contains("option java_package = \"a\\302\\205\\001\u{e9}\\n\";"));
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.options[0].value, value);
+
+        let text = "syntax = \"proto2\";\n\nimport \"a\\\"b.proto\";\n\nmessage A {\n  reserved \"x\\\\y\";\n}\n";
+        let proto = parse_proto_text(text).expect("parse proto text failed");
+        assert_eq!(proto.import[0].proto_file, "a\"b.proto");
+        assert_eq!(proto.messages[0].reserved_names, vec!["x\\y"]);
+        assert_eq!(proto.to_string(), text);
+    }
+}